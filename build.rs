@@ -0,0 +1,4 @@
+// Embed new migrations when they are added
+fn main() {
+    println!("cargo:rerun-if-changed=migrations");
+}
@@ -0,0 +1,41 @@
+-- Initial schema. Databases created before migrations were
+-- introduced already have these tables.
+
+CREATE TABLE IF NOT EXISTS properties(
+    id_property INTEGER PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE,
+    value TEXT NOT NULL);
+
+CREATE TABLE IF NOT EXISTS elections(
+    id_election INTEGER PRIMARY KEY,
+    id TEXT NOT NULL UNIQUE,
+    definition TEXT NOT NULL,
+    closed BOOLEAN NOT NULL);
+
+CREATE TABLE IF NOT EXISTS ballots(
+    id_ballot INTEGER PRIMARY KEY,
+    election INTEGER NOT NULL,
+    height INTEGER NOT NULL,
+    hash BLOB NOT NULL,
+    data TEXT NOT NULL,
+    UNIQUE (election, height));
+
+CREATE TABLE IF NOT EXISTS cmx_frontiers(
+    id_cmx_frontier INTEGER PRIMARY KEY,
+    election INTEGER NOT NULL,
+    height INTEGER NOT NULL,
+    frontier TEXT NOT NULL,
+    UNIQUE (election, height));
+
+CREATE TABLE IF NOT EXISTS cmx_roots(
+    id_cmx_root INTEGER PRIMARY KEY,
+    election INTEGER NOT NULL,
+    height INTEGER NOT NULL,
+    hash BLOB NOT NULL,
+    UNIQUE (election, height));
+
+CREATE TABLE IF NOT EXISTS dnfs(
+    id_dnf INTEGER PRIMARY KEY,
+    election INTEGER NOT NULL,
+    hash BLOB NOT NULL,
+    UNIQUE (election, hash));
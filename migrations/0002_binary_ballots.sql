@@ -0,0 +1,25 @@
+-- Ballots and frontiers are stored as bincode blobs. Rows that are
+-- still JSON text are converted by the server after the migrations.
+
+CREATE TABLE ballots_new(
+    id_ballot INTEGER PRIMARY KEY,
+    election INTEGER NOT NULL,
+    height INTEGER NOT NULL,
+    hash BLOB NOT NULL,
+    data BLOB NOT NULL,
+    UNIQUE (election, height));
+INSERT INTO ballots_new(id_ballot, election, height, hash, data)
+    SELECT id_ballot, election, height, hash, data FROM ballots;
+DROP TABLE ballots;
+ALTER TABLE ballots_new RENAME TO ballots;
+
+CREATE TABLE cmx_frontiers_new(
+    id_cmx_frontier INTEGER PRIMARY KEY,
+    election INTEGER NOT NULL,
+    height INTEGER NOT NULL,
+    frontier BLOB NOT NULL,
+    UNIQUE (election, height));
+INSERT INTO cmx_frontiers_new(election, height, frontier)
+    SELECT election, height, frontier FROM cmx_frontiers;
+DROP TABLE cmx_frontiers;
+ALTER TABLE cmx_frontiers_new RENAME TO cmx_frontiers;
@@ -0,0 +1,8 @@
+-- App state after every committed block. last_ballot is the largest
+-- id_ballot at that height and is used to roll back.
+
+CREATE TABLE app_states(
+    height INTEGER PRIMARY KEY,
+    hash TEXT NOT NULL,
+    time INTEGER NOT NULL,
+    last_ballot INTEGER NOT NULL);
@@ -0,0 +1,6 @@
+-- Block that included each ballot. NULL for ballots stored by older
+-- versions.
+
+ALTER TABLE ballots ADD COLUMN block_height INTEGER;
+ALTER TABLE ballots ADD COLUMN block_time INTEGER;
+ALTER TABLE ballots ADD COLUMN tx_hash TEXT;
@@ -0,0 +1,4 @@
+-- Height of the ballot that spent each nullifier. Rows stored by older
+-- versions are filled by the server after the migrations.
+
+ALTER TABLE dnfs ADD COLUMN height INTEGER;
@@ -0,0 +1,15 @@
+-- Ballots accepted by the REST server and waiting to be broadcast or
+-- committed. status is queued, pending, committed or rejected.
+-- next_attempt is a unix time in seconds.
+
+CREATE TABLE outbox(
+    id_outbox INTEGER PRIMARY KEY,
+    election TEXT NOT NULL,
+    tx_hash TEXT NOT NULL UNIQUE,
+    tx BLOB NOT NULL,
+    status TEXT NOT NULL,
+    attempts INTEGER NOT NULL,
+    next_attempt INTEGER NOT NULL,
+    error TEXT);
+
+CREATE INDEX outbox_next ON outbox(status, next_attempt);
@@ -0,0 +1,14 @@
+-- Ballot outputs in the order they are appended to the cmx tree, to
+-- build witnesses without decoding the ballots. idx starts at 0 after
+-- the initial frontier of the election and height is the ballot height.
+-- Rows of ballots stored by older versions are filled by the server
+-- after the migrations.
+
+CREATE TABLE cmx_leaves(
+    election INTEGER NOT NULL,
+    idx INTEGER NOT NULL,
+    height INTEGER NOT NULL,
+    cmx BLOB NOT NULL,
+    PRIMARY KEY (election, idx));
+
+CREATE INDEX cmx_leaves_cmx ON cmx_leaves(election, cmx);
@@ -0,0 +1,4 @@
+-- Ids of the elections whose cmx roots make the app hash, as a JSON
+-- array in hash order. NULL for the blocks committed before.
+
+ALTER TABLE app_states ADD COLUMN elections TEXT;
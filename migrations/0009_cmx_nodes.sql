@@ -0,0 +1,15 @@
+-- Complete nodes of the cmx tree above the leaves, to build witnesses
+-- without rehashing the tree. idx is the index of the node at its level
+-- and height the ballot height that completed it, 0 for the nodes of the
+-- initial frontier. The leaves are cleared so that the server refills
+-- the leaves and the nodes of every election after the migrations.
+
+CREATE TABLE cmx_nodes(
+    election INTEGER NOT NULL,
+    level INTEGER NOT NULL,
+    idx INTEGER NOT NULL,
+    height INTEGER NOT NULL,
+    hash BLOB NOT NULL,
+    PRIMARY KEY (election, level, idx));
+
+DELETE FROM cmx_leaves;
@@ -0,0 +1,3 @@
+-- Look up the status of a tx by its hash
+
+CREATE INDEX IF NOT EXISTS ballots_tx_hash ON ballots(tx_hash);
@@ -0,0 +1,265 @@
+use anyhow::Result;
+use blake2b_simd::Params;
+use orchard::vote::{Ballot, Frontier};
+use serde::{Deserialize, Serialize};
+use sqlx::{sqlite::SqliteRow, Connection, Row, SqliteConnection};
+use zcash_vote::{
+    db::{load_prop, store_dnf},
+    election::Election,
+};
+
+use crate::{
+    chain::app_hash,
+    db::{
+        decode_ballot, decode_frontier, encode_frontier, get_cmx_roots, get_election,
+        index_nullifiers, init_election, store_ballot, store_election, AppState, BlockRef,
+    },
+    fsck::check_election,
+    store::VoteStore,
+};
+
+pub const ARCHIVE_VERSION: u32 = 2;
+const PERSO_ARCHIVE: &[u8] = b"Zcash_Vote_Arch_";
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ArchivedBallot {
+    pub height: u32,
+    pub sighash: String,
+    pub ballot: Ballot,
+    pub block: Option<BlockRef>,
+}
+
+/// Everything needed to rebuild an election without the rest of the database.
+/// `frontiers` and `roots` are indexed by ballot height, starting at 0
+/// for the initial frontier of the election.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ElectionArchive {
+    pub version: u32,
+    pub election: Election,
+    pub closed: bool,
+    pub ballots: Vec<ArchivedBallot>,
+    pub frontiers: Vec<Frontier>,
+    pub roots: Vec<String>,
+    pub nullifiers: Vec<String>,
+    pub app_state: AppState,
+    /// cmx roots of the elections of the app state, that make its hash
+    pub app_roots: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Manifest {
+    pub election: String,
+    pub ballots: u32,
+    /// blake2b of the bincode serialization of the archive
+    pub hash: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Archive {
+    pub manifest: Manifest,
+    pub archive: ElectionArchive,
+}
+
+impl ElectionArchive {
+    pub fn hash(&self) -> Result<String> {
+        let hash = Params::new()
+            .hash_length(32)
+            .personal(PERSO_ARCHIVE)
+            .hash(&bincode::serialize(self)?);
+        Ok(hex::encode(hash.as_bytes()))
+    }
+}
+
+pub async fn export_election(connection: &mut SqliteConnection, id: &str) -> Result<Archive> {
+    let (id_election, definition, closed) = get_election(connection, id).await?;
+    let election: Election = serde_json::from_str(&definition)?;
+
+    let ballots = sqlx::query(
+        "SELECT height, hash, data, block_height, block_time, tx_hash
+        FROM ballots WHERE election = ?1 ORDER BY height")
+        .bind(id_election)
+        .map(|r: SqliteRow| {
+            let height: u32 = r.get(0);
+            let hash: Vec<u8> = r.get(1);
+            let data: Vec<u8> = r.get(2);
+            let block = BlockRef::from_columns(r.get(3), r.get(4), r.get(5));
+            (height, hash, data, block)
+        })
+        .fetch_all(&mut *connection)
+        .await?;
+    let ballots = ballots
+        .into_iter()
+        .map(|(height, hash, data, block)| {
+            Ok(ArchivedBallot {
+                height,
+                sighash: hex::encode(hash),
+                ballot: decode_ballot(&data)?,
+                block,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let frontiers = sqlx::query(
+        "SELECT frontier FROM cmx_frontiers WHERE election = ?1 ORDER BY height")
+        .bind(id_election)
+        .map(|r: SqliteRow| {
+            let frontier: Vec<u8> = r.get(0);
+            frontier
+        })
+        .fetch_all(&mut *connection)
+        .await?;
+    let frontiers = frontiers
+        .iter()
+        .map(|f| decode_frontier(f))
+        .collect::<Result<Vec<_>>>()?;
+
+    let roots = get_cmx_roots(connection, id_election)
+        .await?
+        .into_iter()
+        .map(hex::encode)
+        .collect::<Vec<_>>();
+
+    let nullifiers: Vec<(Vec<u8>,)> =
+        sqlx::query_as("SELECT hash FROM dnfs WHERE election = ?1 ORDER BY hash")
+            .bind(id_election)
+            .fetch_all(&mut *connection)
+            .await?;
+    let nullifiers = nullifiers.into_iter().map(|(nf,)| hex::encode(nf)).collect();
+
+    let app_state = load_prop(connection, "state").await?
+        .ok_or(anyhow::anyhow!("Missing app state"))?;
+    let app_state = serde_json::from_str::<AppState>(&app_state)?;
+    // every ballot updates the app hash: the latest roots are the roots
+    // of the hash
+    let latest_roots = connection.latest_cmx_roots().await?;
+    let app_roots = app_state
+        .elections
+        .iter()
+        .map(|id| {
+            latest_roots
+                .iter()
+                .find(|(e, _)| e == id)
+                .map(|(_, root)| hex::encode(root))
+                .ok_or(anyhow::anyhow!("Election {id} of the app state has no cmx root"))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let archive = ElectionArchive {
+        version: ARCHIVE_VERSION,
+        election,
+        closed,
+        ballots,
+        frontiers,
+        roots,
+        nullifiers,
+        app_state,
+        app_roots,
+    };
+    let manifest = Manifest {
+        election: id.to_string(),
+        ballots: archive.ballots.len() as u32,
+        hash: archive.hash()?,
+    };
+    Ok(Archive { manifest, archive })
+}
+
+/// Check the app state of the archive against its roots, and the root of
+/// the election against the recomputed `latest_root`
+fn check_app_state(archive: &ElectionArchive, latest_root: Option<String>, num_ballots: u32) -> Vec<String> {
+    let mut errors = vec![];
+    let app_state = &archive.app_state;
+    if app_state.elections.len() != archive.app_roots.len() {
+        errors.push(format!(
+            "{} app roots for {} elections in the app state",
+            archive.app_roots.len(),
+            app_state.elections.len()
+        ));
+        return errors;
+    }
+    let roots = archive.app_roots.iter().map(hex::decode).collect::<Result<Vec<_>, _>>();
+    match roots {
+        Ok(roots) if hex::encode(app_hash(&roots)) == app_state.hash => {}
+        Ok(_) => errors.push(format!("app hash {} does not match the app roots", app_state.hash)),
+        Err(e) => errors.push(format!("invalid app root: {e}")),
+    }
+    let id = archive.election.id();
+    match app_state.elections.iter().position(|e| *e == id) {
+        Some(i) if Some(&archive.app_roots[i]) != latest_root.as_ref() => {
+            errors.push("latest cmx root does not match the app state".to_string())
+        }
+        Some(_) => {}
+        // added after the last ballot of the app state
+        None if num_ballots != 0 => errors.push("election is missing from the app state".to_string()),
+        None => {}
+    }
+    errors
+}
+
+/// Rebuild the election of an archive and verify it with the fsck checks.
+/// Nothing is written if verification fails. The app state of the database
+/// is left untouched because it covers every election.
+pub async fn import_election(connection: &mut SqliteConnection, archive: &Archive) -> Result<u32> {
+    let Archive { manifest, archive } = archive;
+    if archive.version != ARCHIVE_VERSION {
+        anyhow::bail!("Unsupported archive version {}", archive.version);
+    }
+    if archive.hash()? != manifest.hash {
+        anyhow::bail!("Archive hash does not match the manifest");
+    }
+    let id = archive.election.id();
+    if manifest.election != id || manifest.ballots as usize != archive.ballots.len() {
+        anyhow::bail!("Archive content does not match the manifest");
+    }
+    match get_election(connection, &id).await {
+        Ok(_) => anyhow::bail!("Election {id} already exists"),
+        Err(e) if matches!(e.downcast_ref::<sqlx::Error>(), Some(sqlx::Error::RowNotFound)) => {}
+        Err(e) => return Err(e),
+    }
+    let n = archive.ballots.len();
+    if archive.frontiers.len() != n + 1 || archive.roots.len() != n + 1 {
+        anyhow::bail!("Archive must have one frontier and cmx root per ballot height");
+    }
+    let initial_frontier = archive.election.cmx_frontier.as_ref()
+        .ok_or(anyhow::anyhow!("Election has no cmx frontier"))?;
+    if encode_frontier(initial_frontier)? != encode_frontier(&archive.frontiers[0])? {
+        anyhow::bail!("Initial frontier does not match the election");
+    }
+
+    let mut db_tx = connection.begin().await?;
+    let id_election = init_election(&mut db_tx, &archive.election).await?;
+    store_election(&mut db_tx, &archive.election, archive.closed).await?;
+    for (b, (frontier, root)) in archive
+        .ballots
+        .iter()
+        .zip(archive.frontiers.iter().zip(archive.roots.iter()).skip(1))
+    {
+        store_ballot(&mut db_tx, id_election, b.height, &b.ballot, &hex::decode(root)?, b.block.as_ref()).await?;
+        db_tx.store_frontier(id_election, b.height, frontier).await?;
+    }
+    for nf in archive.nullifiers.iter() {
+        store_dnf(&mut db_tx, id_election, &hex::decode(nf)?).await?;
+    }
+
+    index_nullifiers(&mut db_tx).await?;
+
+    let check = check_election(&mut db_tx, id_election).await?;
+    let mut errors = check.errors;
+    for b in archive.ballots.iter() {
+        if b.sighash != hex::encode(b.ballot.data.sighash()?) {
+            errors.push(format!("ballot {} sighash does not match the archive", b.height));
+        }
+    }
+    let latest_root = check.latest_root.map(hex::encode);
+    if latest_root.as_ref() != archive.roots.last() {
+        errors.push("latest cmx root does not match the archive".to_string());
+    }
+    errors.extend(check_app_state(archive, latest_root, check.num_ballots));
+    if !errors.is_empty() {
+        for e in errors.iter() {
+            tracing::error!("{e}");
+        }
+        anyhow::bail!("Archive verification failed: {} errors", errors.len());
+    }
+    db_tx.commit().await?;
+    Ok(id_election)
+}
@@ -0,0 +1,101 @@
+use std::collections::HashSet;
+
+use anyhow::Result;
+use orchard::vote::{Ballot, OrchardHash};
+use zcash_vote::{
+    as_byte256,
+    election::{Election, BALLOT_VK},
+};
+
+/// Replay an election from the REST API of a node
+///
+/// Every ballot is validated again and the cmx tree is rebuilt from the
+/// initial frontier of the election. The roots must match the node's.
+/// Returns every discrepancy found.
+pub async fn audit(url: &str, id: &str) -> Result<Vec<String>> {
+    let client = reqwest::Client::new();
+    let election: Election = client
+        .get(format!("{url}/election/{id}"))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    // the node could serve another election under this id
+    if election.id() != id {
+        anyhow::bail!("Election id mismatch: {} != {id}", election.id());
+    }
+    let n: u32 = client
+        .get(format!("{url}/election/{id}/num_ballots"))
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?
+        .parse()?;
+    let node_roots: Vec<String> = client
+        .get(format!("{url}/election/{id}/cmx_roots"))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    tracing::info!("Auditing election {} with {} ballots", election.id(), n);
+
+    let mut errors = vec![];
+    let mut cmx_frontier = election
+        .cmx_frontier
+        .clone()
+        .ok_or(anyhow::anyhow!("Election has no cmx frontier"))?;
+    let mut roots = vec![hex::encode(cmx_frontier.root())];
+    let mut dnfs = HashSet::new();
+
+    for height in 1..=n {
+        let ballot: Ballot = client
+            .get(format!("{url}/election/{id}/ballot/height/{height}"))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        match orchard::vote::validate_ballot(ballot.clone(), election.signature_required, &BALLOT_VK) {
+            Ok(data) => {
+                if data.anchors.nf != election.nf.0 {
+                    errors.push(format!("Ballot {height}: incorrect nullifier root"));
+                }
+                if !roots.contains(&hex::encode(&data.anchors.cmx)) {
+                    errors.push(format!("Ballot {height}: unknown cmx root"));
+                }
+            }
+            Err(e) => errors.push(format!("Ballot {height}: {e}")),
+        }
+
+        for action in ballot.data.actions.iter() {
+            if !dnfs.insert(action.nf.clone()) {
+                errors.push(format!(
+                    "Ballot {height}: duplicate nullifier {}",
+                    hex::encode(&action.nf)
+                ));
+            }
+            cmx_frontier.append(OrchardHash(as_byte256(&action.cmx)));
+        }
+        roots.push(hex::encode(cmx_frontier.root()));
+        tracing::info!("Ballot {height} checked");
+    }
+
+    if roots.len() != node_roots.len() {
+        errors.push(format!(
+            "Number of cmx roots: {} != {}",
+            roots.len(),
+            node_roots.len()
+        ));
+    }
+    for (i, (root, node_root)) in roots.iter().zip(node_roots.iter()).enumerate() {
+        if root != node_root {
+            errors.push(format!("cmx root #{i}: {root} != {node_root}"));
+        }
+    }
+
+    Ok(errors)
+}
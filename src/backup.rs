@@ -0,0 +1,44 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::store::VoteStore;
+
+/// Metadata written next to a backup, in a `.json` file with the same name
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BackupInfo {
+    pub path: String,
+    pub height: u32,
+    pub app_hash: String,
+    /// unix timestamp in seconds
+    pub time: u64,
+}
+
+pub fn backup_path(dir: &str, height: u32) -> PathBuf {
+    Path::new(dir).join(format!("vote-{height}.db"))
+}
+
+/// Copy the database to `path` and write its metadata.
+/// The store must not be in the middle of a block.
+pub async fn backup<S: VoteStore>(store: &mut S, path: &Path) -> Result<BackupInfo> {
+    if path.exists() {
+        anyhow::bail!("{} already exists", path.display());
+    }
+    let app_state = store.app_state().await?;
+    let path_str = path.to_string_lossy().to_string();
+    store.backup(&path_str).await?;
+
+    let info = BackupInfo {
+        path: path_str,
+        height: app_state.height,
+        app_hash: app_state.hash,
+        time: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+    };
+    std::fs::write(path.with_extension("json"), serde_json::to_string_pretty(&info)?)?;
+    tracing::info!("Backup at height {} to {}", info.height, info.path);
+    Ok(info)
+}
@@ -0,0 +1,33 @@
+use zcash_vote_server::audit::audit;
+
+#[rocket::main]
+pub async fn main() {
+    let subscriber = tracing_subscriber::fmt()
+        .with_ansi(false)
+        .compact()
+        .finish();
+    tracing::subscriber::set_global_default(subscriber).unwrap();
+
+    let args: Vec<String> = std::env::args().collect();
+    let (Some(url), Some(id)) = (args.get(1), args.get(2)) else {
+        eprintln!("Usage: zcash-vote-audit <server url> <election id>");
+        std::process::exit(2);
+    };
+
+    // exit code 1 is for discrepancies
+    let errors = match audit(url.trim_end_matches('/'), id).await {
+        Ok(errors) => errors,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(2);
+        }
+    };
+    for e in errors.iter() {
+        println!("{e}");
+    }
+    if !errors.is_empty() {
+        eprintln!("{} errors found", errors.len());
+        std::process::exit(1);
+    }
+    println!("Election {id} verified");
+}
@@ -1,57 +1,250 @@
 use anyhow::Result;
 use blake2b_simd::Params;
-use sqlx::{sqlite::SqliteRow, Row, SqliteConnection, SqlitePool};
+use sha2::{Digest, Sha256};
+use rocket::tokio::{runtime::Builder, sync::broadcast};
+use sqlx::{SqliteConnection, SqlitePool};
 use std::{
-    collections::{hash_map::Entry, HashMap, HashSet},
-    sync::mpsc::{channel, Receiver, Sender},
+    collections::{hash_map::Entry, HashMap, HashSet, VecDeque},
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
 };
 use zcash_vote::{
     as_byte256,
-    db::{load_prop, store_dnf, store_prop},
     election::{Election, BALLOT_VK},
 };
 
-use orchard::vote::{Ballot, Frontier, OrchardHash};
+use orchard::vote::{Ballot, OrchardHash};
 use tendermint_abci::Application;
 use tendermint_proto::abci::{
-    ExecTxResult, RequestCheckTx, RequestFinalizeBlock, RequestInfo, RequestPrepareProposal,
+    CheckTxType, ExecTxResult, RequestCheckTx, RequestFinalizeBlock, RequestInfo, RequestPrepareProposal,
     RequestQuery, ResponseCheckTx, ResponseCommit, ResponseFinalizeBlock, ResponseInfo,
     ResponsePrepareProposal, ResponseQuery,
 };
 
 use crate::{
-    db::{check_cmx_root, get_election, store_ballot, AppState},
+    backup::{backup, backup_path, BackupInfo},
+    db::{AppState, BlockRef},
     routes::Tx,
+    store::VoteStore,
 };
 
 pub enum Command {
     Stop,
     Info(Sender<AppState>),
+    /// Height and time (unix seconds) of the block being finalized
+    BeginBlock(u32, i64),
     CheckBallot(String, Ballot, Sender<Result<String, String>>),
     PrepareProposal(String, Ballot, Sender<Option<String>>),
-    FinalizeBallot(String, Ballot, Sender<Result<String, String>>),
+    /// Election id, ballot and tx hash
+    FinalizeBallot(String, Ballot, String, Sender<Result<String, String>>),
     Commit(Sender<AppState>),
+    /// Back up the database into a directory after the current block is committed
+    Backup(String, Sender<Result<BackupInfo, String>>),
 }
 
 #[derive(Clone)]
 pub struct VoteChain {
     cmd_tx: Sender<Command>,
+    rejections: Rejections,
+    mempool: Mempool,
+    events: broadcast::Sender<TxEvent>,
+    proof_check: ProofCheck,
+}
+
+/// Check of the zkp and signatures of a ballot, `check_ballot_proofs`
+/// except in tests
+pub type ProofCheck = fn(&Ballot, &Election) -> Result<(), BallotError>;
+
+/// Result of a ballot tx, sent when its block is committed
+#[derive(Clone, Debug)]
+pub struct TxEvent {
+    pub tx_hash: String,
+    pub error: Option<String>,
+}
+
+/// Errors of the most recent txs rejected by check_tx or finalize_block,
+/// by tx hash
+#[derive(Clone, Default)]
+pub struct Rejections(Arc<Mutex<(HashMap<String, String>, VecDeque<String>)>>);
+
+const MAX_REJECTIONS: usize = 10_000;
+
+impl Rejections {
+    pub fn insert(&self, tx_hash: String, error: String) {
+        let mut guard = self.0.lock().unwrap();
+        let (errors, order) = &mut *guard;
+        if errors.insert(tx_hash.clone(), error).is_none() {
+            order.push_back(tx_hash);
+        }
+        if order.len() > MAX_REJECTIONS {
+            if let Some(oldest) = order.pop_front() {
+                errors.remove(&oldest);
+            }
+        }
+    }
+
+    pub fn get(&self, tx_hash: &str) -> Option<String> {
+        self.0.lock().unwrap().0.get(tx_hash).cloned()
+    }
+}
+
+/// The txs of the mempool of the node: the txs that passed check_tx and
+/// are not in a block yet, by tx hash, with their election and nullifiers.
+/// CometBFT only lists the first 100 txs of its mempool, so the app keeps
+/// its own view. A tx leaves it when it is in a block, fails a recheck or
+/// has not been checked for `MAX_MEMPOOL_AGE`.
+#[derive(Clone, Default)]
+pub struct Mempool(Arc<Mutex<(HashMap<String, MempoolTx>, VecDeque<String>)>>);
+
+struct MempoolTx {
+    election: String,
+    nullifiers: Vec<Vec<u8>>,
+    checked: Instant,
+}
+
+const MAX_MEMPOOL: usize = 10_000;
+/// CometBFT rechecks the txs of its mempool after every block
+const MAX_MEMPOOL_AGE: Duration = Duration::from_secs(3600);
+
+impl Mempool {
+    /// Add a tx or refresh the time of its last check
+    pub fn insert(&self, tx_hash: String, election: String, nullifiers: Vec<Vec<u8>>) {
+        let mut guard = self.0.lock().unwrap();
+        let (txs, order) = &mut *guard;
+        let tx = MempoolTx {
+            election,
+            nullifiers,
+            checked: Instant::now(),
+        };
+        if txs.insert(tx_hash.clone(), tx).is_some() {
+            order.retain(|h| h != &tx_hash);
+        }
+        order.push_back(tx_hash);
+        // in case the node drops txs without a recheck
+        if order.len() > MAX_MEMPOOL {
+            if let Some(oldest) = order.pop_front() {
+                txs.remove(&oldest);
+            }
+        }
+        Self::expire(txs, order, MAX_MEMPOOL_AGE);
+    }
+
+    pub fn remove(&self, tx_hash: &str) {
+        let mut guard = self.0.lock().unwrap();
+        let (txs, order) = &mut *guard;
+        if txs.remove(tx_hash).is_some() {
+            order.retain(|h| h != tx_hash);
+        }
+    }
+
+    pub fn contains(&self, tx_hash: &str) -> bool {
+        self.prune(MAX_MEMPOOL_AGE);
+        self.0.lock().unwrap().0.contains_key(tx_hash)
+    }
+
+    /// Whether a tx of the election spends the nullifier `nf`
+    pub fn spends(&self, election: &str, nf: &[u8]) -> bool {
+        self.prune(MAX_MEMPOOL_AGE);
+        self.0
+            .lock()
+            .unwrap()
+            .0
+            .values()
+            .any(|tx| tx.election == election && tx.nullifiers.iter().any(|n| n == nf))
+    }
+
+    /// Remove the txs that have not been checked for `max_age`
+    pub fn prune(&self, max_age: Duration) {
+        let mut guard = self.0.lock().unwrap();
+        let (txs, order) = &mut *guard;
+        Self::expire(txs, order, max_age);
+    }
+
+    fn expire(txs: &mut HashMap<String, MempoolTx>, order: &mut VecDeque<String>, max_age: Duration) {
+        // the order is by last check
+        while let Some(oldest) = order.front() {
+            if txs.get(oldest).is_some_and(|tx| tx.checked.elapsed() < max_age) {
+                break;
+            }
+            if let Some(oldest) = order.pop_front() {
+                txs.remove(&oldest);
+            }
+        }
+    }
 }
 
 impl VoteChain {
     pub async fn new(pool: SqlitePool) -> (Self, VoteChainRunner) {
-        let (cmd_tx, cmd_rx) = channel::<Command>();
-        let s = Self { cmd_tx };
         let connection = pool.acquire().await.unwrap();
         let connection = connection.detach();
+        Self::with_store(connection)
+    }
+
+    pub fn with_store<S: VoteStore>(store: S) -> (Self, VoteChainRunner<S>) {
+        Self::with_proof_check(store, check_ballot_proofs)
+    }
+
+    pub fn with_proof_check<S: VoteStore>(
+        store: S,
+        proof_check: ProofCheck,
+    ) -> (Self, VoteChainRunner<S>) {
+        let (cmd_tx, cmd_rx) = channel::<Command>();
+        let (events, _) = broadcast::channel(1024);
+        let s = Self {
+            cmd_tx,
+            rejections: Rejections::default(),
+            mempool: Mempool::default(),
+            events: events.clone(),
+            proof_check,
+        };
         let r = VoteChainRunner {
-            connection,
+            store,
             cmd_rx,
+            events,
+            proof_check,
+            block_tx_height: 0,
+            block_events: vec![],
             check_cache: HashMap::new(),
             dnfs: HashSet::new(),
+            in_block: false,
+            block_height: 0,
+            block_time: 0,
+            pending_backups: vec![],
         };
         (s, r)
     }
+
+    /// Subscribe to the results of the txs of the next committed blocks
+    pub fn subscribe(&self) -> broadcast::Receiver<TxEvent> {
+        self.events.subscribe()
+    }
+
+    /// Error of a recently rejected tx
+    pub fn rejection(&self, tx_hash: &str) -> Option<String> {
+        self.rejections.get(tx_hash)
+    }
+
+    /// The txs of the mempool of the node
+    pub fn mempool(&self) -> &Mempool {
+        &self.mempool
+    }
+
+    pub fn proof_check(&self) -> ProofCheck {
+        self.proof_check
+    }
+
+    /// Blocks until the backup is written, which can be after the next commit
+    pub fn backup(&self, dir: &str) -> Result<BackupInfo> {
+        let (tx_result, rx_result) = channel();
+        self.cmd_tx
+            .send(Command::Backup(dir.to_string(), tx_result))
+            .map_err(anyhow::Error::msg)?;
+        rx_result.recv()?.map_err(anyhow::Error::msg)
+    }
 }
 
 impl Application for VoteChain {
@@ -80,30 +273,47 @@ impl Application for VoteChain {
     fn check_tx(&self, request: RequestCheckTx) -> ResponseCheckTx {
         tracing::info!(
             "check_tx --> {} TYPE {}",
-            hex::encode(&request.tx[0..16]),
+            hex::encode(&request.tx[0..request.tx.len().min(16)]),
             request.r#type
         );
 
-        let Tx { id, ballot } = bincode::deserialize(&request.tx).unwrap();
+        let hash = tx_hash(&request.tx);
+        let Ok(Tx { id, ballot }) = bincode::deserialize(&request.tx) else {
+            tracing::error!("check_tx failed: invalid tx");
+            self.rejections.insert(hash, "Invalid tx".to_string());
+            return ResponseCheckTx {
+                code: 1,
+                data: "Invalid tx".into(),
+                ..Default::default()
+            };
+        };
+        let nullifiers = ballot.data.actions.iter().map(|a| a.nf.clone()).collect();
         let (tx_result, rx_result) = channel();
         self.cmd_tx
-            .send(Command::CheckBallot(id, ballot, tx_result))
+            .send(Command::CheckBallot(id.clone(), ballot, tx_result))
             .map_err(anyhow::Error::msg)
             .unwrap();
 
         let res = rx_result.recv().unwrap();
         match res {
-            Ok(hash) => {
-                tracing::info!("check_tx ok: {}", hash);
+            Ok(sighash) => {
+                tracing::info!("check_tx ok: {}", sighash);
+                self.mempool.insert(hash, id, nullifiers);
                 ResponseCheckTx {
                     code: 0,
-                    data: hash.into(),
+                    data: sighash.into(),
                     ..Default::default()
                 }
             }
 
             Err(message) => {
                 tracing::error!("check_tx failed: {}", message);
+                // a failed recheck evicts the tx from the mempool,
+                // a new check of a tx does not affect its previous copy
+                if request.r#type == CheckTxType::Recheck as i32 {
+                    self.mempool.remove(&hash);
+                }
+                self.rejections.insert(hash, message.clone());
                 ResponseCheckTx {
                     code: 1,
                     data: message.into(),
@@ -116,7 +326,10 @@ impl Application for VoteChain {
     fn prepare_proposal(&self, request: RequestPrepareProposal) -> ResponsePrepareProposal {
         let mut filtered_txs = vec![];
         for tx in request.txs.into_iter() {
-            let Tx { id, ballot } = bincode::deserialize(&tx).unwrap();
+            let Ok(Tx { id, ballot }) = bincode::deserialize::<Tx>(&tx) else {
+                tracing::error!("prepare_proposal: invalid tx");
+                continue;
+            };
             let sighash = hex::encode(ballot.data.sighash().unwrap());
             let (tx_result, rx_result) = channel();
             self.cmd_tx
@@ -136,15 +349,27 @@ impl Application for VoteChain {
     }
 
     fn finalize_block(&self, request: RequestFinalizeBlock) -> ResponseFinalizeBlock {
+        let time = request.time.as_ref().map(|t| t.seconds).unwrap_or_default();
+        self.cmd_tx
+            .send(Command::BeginBlock(request.height as u32, time))
+            .map_err(anyhow::Error::msg)
+            .unwrap();
+
         let mut tx_results = vec![];
         for tx in request.txs.iter() {
-            let Tx { id, ballot } = bincode::deserialize(tx).unwrap();
-            let (tx_result, rx_result) = channel();
-            self.cmd_tx
-                .send(Command::FinalizeBallot(id, ballot, tx_result))
-                .map_err(anyhow::Error::msg)
-                .unwrap();
-            let res = rx_result.recv().unwrap();
+            let hash = tx_hash(tx);
+            self.mempool.remove(&hash);
+            let res = match bincode::deserialize::<Tx>(tx) {
+                Ok(Tx { id, ballot }) => {
+                    let (tx_result, rx_result) = channel();
+                    self.cmd_tx
+                        .send(Command::FinalizeBallot(id, ballot, hash.clone(), tx_result))
+                        .map_err(anyhow::Error::msg)
+                        .unwrap();
+                    rx_result.recv().unwrap()
+                }
+                Err(_) => Err("Invalid tx".to_string()),
+            };
             tracing::info!("finalize_block: {:?}", res);
 
             let tx_result = match res {
@@ -153,11 +378,14 @@ impl Application for VoteChain {
                     log: "Validated".to_string(),
                     ..Default::default()
                 },
-                Err(err) => ExecTxResult {
-                    code: 1,
-                    log: format!("Validation failed: {}", err),
-                    ..Default::default()
-                },
+                Err(err) => {
+                    self.rejections.insert(hash, err.clone());
+                    ExecTxResult {
+                        code: 1,
+                        log: format!("Validation failed: {}", err),
+                        ..Default::default()
+                    }
+                }
             };
             tx_results.push(tx_result);
         }
@@ -192,74 +420,156 @@ impl Application for VoteChain {
     }
 }
 
-pub struct VoteChainRunner {
-    connection: SqliteConnection,
+/// Why a ballot fails check_tx
+#[derive(Debug)]
+pub enum BallotError {
+    UnknownElection,
+    ElectionClosed,
+    IncorrectNullifierRoot,
+    InvalidCmxRoot,
+    DoubleSpend,
+    /// zkp or signatures
+    InvalidProof(String),
+    /// the ballot could not be checked
+    Store(anyhow::Error),
+}
+
+impl std::fmt::Display for BallotError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BallotError::UnknownElection => f.write_str("Unknown election"),
+            BallotError::ElectionClosed => f.write_str("Election is closed"),
+            BallotError::IncorrectNullifierRoot => f.write_str("Incorrect nullifier root"),
+            BallotError::InvalidCmxRoot => f.write_str("Invalid cmx root"),
+            BallotError::DoubleSpend => f.write_str("Duplicate nullifier: double spend"),
+            BallotError::InvalidProof(e) => f.write_str(e),
+            BallotError::Store(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for BallotError {}
+
+// sqlx errors, except a missing row, are failures of the store
+fn store_error(e: anyhow::Error, invalid: BallotError) -> BallotError {
+    match e.downcast_ref::<sqlx::Error>() {
+        None | Some(sqlx::Error::RowNotFound) => invalid,
+        Some(_) => BallotError::Store(e),
+    }
+}
+
+/// The checks of check_tx that depend on the state: the election is open,
+/// the roots match and the nullifiers are not spent.
+/// Returns the election, for `check_ballot_proofs`.
+pub async fn check_ballot_state<S: VoteStore>(
+    store: &mut S,
+    id: &str,
+    ballot: &Ballot,
+) -> Result<Election, BallotError> {
+    let (id_election, election, closed) = store
+        .get_election(id)
+        .await
+        .map_err(|e| store_error(e, BallotError::UnknownElection))?;
+    if closed {
+        return Err(BallotError::ElectionClosed);
+    }
+    let election = serde_json::from_str::<Election>(&election)
+        .map_err(|e| BallotError::Store(e.into()))?;
+
+    // check that the public data matches with the election params
+    // nf_root & cmx_root
+    let data = &ballot.data;
+    if data.anchors.nf != election.nf.0 {
+        return Err(BallotError::IncorrectNullifierRoot);
+    }
+    store
+        .check_cmx_root(id_election, &data.anchors.cmx)
+        .await
+        .map_err(|e| store_error(e, BallotError::InvalidCmxRoot))?;
+
+    // check that we are not double spending a previous note
+    for action in data.actions.iter() {
+        let exists = store
+            .nullifier_exists(id_election, &action.nf)
+            .await
+            .map_err(BallotError::Store)?;
+        if exists {
+            return Err(BallotError::DoubleSpend);
+        }
+    }
+    Ok(election)
+}
+
+/// Check the ballot zkp and signatures
+pub fn check_ballot_proofs(ballot: &Ballot, election: &Election) -> Result<(), BallotError> {
+    orchard::vote::validate_ballot(ballot.clone(), election.signature_required, &BALLOT_VK)
+        .map_err(|e| BallotError::InvalidProof(e.to_string()))?;
+    Ok(())
+}
+
+/// The checks of check_tx, cheapest first
+pub async fn check_ballot<S: VoteStore>(store: &mut S, id: &str, ballot: &Ballot) -> Result<(), BallotError> {
+    let election = check_ballot_state(store, id, ballot).await?;
+    check_ballot_proofs(ballot, &election)
+}
+
+pub struct VoteChainRunner<S: VoteStore = SqliteConnection> {
+    store: S,
     cmd_rx: Receiver<Command>,
+    events: broadcast::Sender<TxEvent>,
+    proof_check: ProofCheck,
+    // blocks below this height only keep their last ballot
+    block_tx_height: u32,
+    // results of the txs of the current block
+    block_events: Vec<TxEvent>,
     check_cache: HashMap<String, Result<String, String>>,
     dnfs: HashSet<String>,
+    // a block is being finalized and is not committed yet
+    in_block: bool,
+    block_height: u32,
+    block_time: i64,
+    pending_backups: Vec<(String, Sender<Result<BackupInfo, String>>)>,
 }
 
-impl VoteChainRunner {
-    async fn get_state(connection: &mut SqliteConnection) -> Result<AppState> {
-        let s = load_prop(&mut *connection, "state").await?.unwrap();
-        Ok(serde_json::from_str::<AppState>(&s)?)
+impl<S: VoteStore> VoteChainRunner<S> {
+    /// Blocks below `height` are finalized as before the block transaction:
+    /// each ballot discards the writes of the previous ballots of its block,
+    /// and a rejected ballot keeps its writes. Chains that started with
+    /// the older versions need it to replay their blocks with the same
+    /// app hashes.
+    pub fn with_block_tx_height(mut self, height: u32) -> Self {
+        self.block_tx_height = height;
+        self
     }
 
     async fn process_command(&mut self, cmd: &Command) -> Result<()> {
         match cmd {
             Command::Stop => return Ok(()), // handled by caller
             Command::Info(result) => {
-                let app_state = Self::get_state(&mut self.connection).await?;
+                let app_state = self.store.app_state().await?;
                 result.send(app_state).unwrap();
             }
+            Command::BeginBlock(height, time) => {
+                tracing::info!("Block {height} at {time}");
+                self.block_height = *height;
+                self.block_time = *time;
+                // a block that was finalized but not committed is replaced
+                self.block_events.clear();
+                self.in_block = true;
+                self.store.begin().await?;
+            }
             Command::CheckBallot(id, ballot, result) => {
                 let sighash = hex::encode(ballot.data.sighash().unwrap());
                 let r = match self.check_cache.entry(sighash.clone()) {
                     Entry::Occupied(r) => r.get().clone(),
                     Entry::Vacant(ve) => {
+                        tracing::info!("Checking ballot {}", sighash);
                         let res = async {
-                            let (id_election, election, closed) =
-                                get_election(&mut self.connection, id).await.map_err(anyhow::Error::msg)?;
-                            if closed {
-                                anyhow::bail!("Election is closed");
-                            }
-                            let election = serde_json::from_str::<Election>(&election)
-                                .map_err(anyhow::Error::msg)?;
-                            // check ballot zkp, and signatures
-                            let data = orchard::vote::validate_ballot(
-                                ballot.clone(),
-                                election.signature_required,
-                                &BALLOT_VK,
-                            )
-                            .map_err(anyhow::Error::msg)?;
-                            tracing::info!("Checking ballot {}", sighash);
-
-                            // check that the public data matches with the election params
-                            // nf_root & cmx_root
-                            if data.anchors.nf != election.nf.0 {
-                                anyhow::bail!("Incorrect nullifier root");
-                            }
-                            check_cmx_root(&mut self.connection, id_election, &data.anchors.cmx)
-                                .await.map_err(anyhow::Error::msg)?;
-
-                            // check that we are not double spending a previous note
-                            for action in data.actions.iter() {
-                                let dnf = &action.nf;
-                                let exists = sqlx::query(
-                                    "SELECT 1 FROM dnfs WHERE election = ?1 AND hash = ?2",
-                                )
-                                .bind(election.id())
-                                .bind(dnf)
-                                .fetch_optional(&mut self.connection)
-                                .await?
-                                .is_some();
-                                if exists {
-                                    anyhow::bail!("Duplicate nullifier: double spend");
-                                }
-                            }
-                            Ok::<_, anyhow::Error>(sighash.clone())
+                            let election = check_ballot_state(&mut self.store, id, ballot).await?;
+                            (self.proof_check)(ballot, &election)
                         };
-                        let r = res.await.map_err(|e| e.to_string());
+                        let res = res.await.map(|_| sighash.clone());
+                        let r = res.map_err(|e| e.to_string());
                         ve.insert_entry(r.clone());
                         r
                     }
@@ -268,24 +578,28 @@ impl VoteChainRunner {
                 result.send(r).unwrap();
             }
             Command::PrepareProposal(_, ballot, sender) => {
+                let mut double_spend = false;
                 for a in ballot.data.actions.iter() {
                     let dnf = hex::encode(&a.nf);
                     let new_spend = self.dnfs.insert(dnf);
-                    if !new_spend {
-                        sender.send(Some("Double spend".to_string()))?;
-                    } else {
-                        sender.send(None)?;
-                    }
+                    double_spend |= !new_spend;
+                }
+                if double_spend {
+                    sender.send(Some("Double spend".to_string()))?;
+                } else {
+                    sender.send(None)?;
                 }
             }
-            Command::FinalizeBallot(id, ballot, result) => {
+            Command::FinalizeBallot(id, ballot, tx_hash, result) => {
+                let legacy = self.block_height < self.block_tx_height;
                 let res = async {
-                    let _ = sqlx::query("ROLLBACK").execute(&mut self.connection).await;
-                    sqlx::query("BEGIN TRANSACTION")
-                        .execute(&mut self.connection)
-                        .await?;
+                    if legacy {
+                        self.store.begin().await?;
+                    } else {
+                        self.store.savepoint().await?;
+                    }
 
-                    let (id_election, _, closed) = get_election(&mut self.connection, id).await?;
+                    let (id_election, _, closed) = self.store.get_election(id).await?;
                     if closed {
                         anyhow::bail!("Election is closed");
                     }
@@ -294,24 +608,14 @@ impl VoteChainRunner {
                     // double spends were checked in check_tx
                     let data = &ballot.data;
 
-                    let (height,): (u32,) =
-                        sqlx::query_as("SELECT MAX(height) FROM cmx_frontiers WHERE election = ?1")
-                            .bind(id_election)
-                            .fetch_one(&mut self.connection)
-                            .await?;
+                    let height = self.store.frontier_height(id_election).await?;
 
                     let cmx_frontier = {
                         // calculate the new cmx_frontier
-                        let mut cmx_frontier = sqlx::query(
-                            "SELECT frontier FROM cmx_frontiers WHERE election = ?1 AND height = ?2")
-                            .bind(id_election).bind(height)
-                            .map(|r: SqliteRow| {
-                                let cmx_frontier: String = r.get(0);
-                                serde_json::from_str::<Frontier>(&cmx_frontier)
-                            }).fetch_one(&mut self.connection).await??;
+                        let mut cmx_frontier = self.store.frontier_at(id_election, height).await?;
                         for action in data.actions.iter() {
                             cmx_frontier.append(OrchardHash(as_byte256(&action.cmx)));
-                            store_dnf(&mut self.connection, id_election, &action.nf)
+                            self.store.store_nullifier(id_election, &action.nf, height + 1)
                                 .await
                                 .map_err(|_| {
                                     anyhow::anyhow!("Duplicate nullifier: double spend")
@@ -321,64 +625,29 @@ impl VoteChainRunner {
                     };
 
                     let cmx_root = cmx_frontier.root();
-                    {
-                        // store the new cmx_frontier
-                        let cmx_frontier = serde_json::to_string(&cmx_frontier)?;
-                        sqlx::query(
-                            "INSERT INTO cmx_frontiers(election, height, frontier)
-                            VALUES (?1, ?2, ?3)",
-                        )
-                        .bind(id_election)
-                        .bind(height + 1)
-                        .bind(&cmx_frontier)
-                        .execute(&mut self.connection)
-                        .await?;
-                    }
+                    // store the new cmx_frontier
+                    self.store.store_frontier(id_election, height + 1, &cmx_frontier).await?;
 
-                    let height = crate::db::get_num_ballots(&mut self.connection, id_election).await?;
-                    tracing::info!("ballot height: {height}");
-                    store_ballot(&mut self.connection, id_election, height + 1, ballot, &cmx_root).await?;
+                    let block = BlockRef {
+                        height: self.block_height,
+                        time: self.block_time,
+                        tx_hash: tx_hash.clone(),
+                    };
+                    self.store.append_ballot(id_election, ballot, &cmx_root, &block).await?;
                     let sighash = hex::encode(data.sighash()?);
                     tracing::info!("election: {id_election} sighash: {sighash}");
 
-                    let hashes = sqlx::query(
-                        "SELECT t1.hash
-                        FROM cmx_roots t1
-                        JOIN (
-                            SELECT election, MAX(height) AS max_height
-                            FROM cmx_roots
-                            GROUP BY election
-                        ) t2
-                        ON t1.election = t2.election AND t1.height = t2.max_height
-                        JOIN elections e ON t1.election = e.id_election
-                        ORDER BY e.id"
-                    )
-                    .map(|r: SqliteRow| {
-                        let hash: Vec<u8> = r.get(0);
-                        hash
-                    })
-                    .fetch_all(&mut self.connection)
-                    .await?;
-                    let mut hasher = Params::new()
-                        .hash_length(32)
-                        .personal(PERSO_VOTE_BFT)
-                        .to_state();
-                    for h in hashes.iter() {
-                        hasher.update(h);
-                    }
-                    let hash = hasher.finalize();
-                    let hash = hash.as_bytes().to_vec();
+                    let (elections, hashes): (Vec<_>, Vec<_>) =
+                        self.store.latest_cmx_roots().await?.into_iter().unzip();
+                    let hash = app_hash(&hashes);
 
-                    let app_state = Self::get_state(&mut self.connection).await?;
+                    let app_state = self.store.app_state().await?;
                     let app_state = AppState {
                         hash: hex::encode(&hash),
+                        elections,
                         ..app_state
                     };
-                    store_prop(
-                        &mut self.connection,
-                        "state",
-                        &serde_json::to_string(&app_state).unwrap(),
-                    ).await?;
+                    self.store.store_app_state(&app_state).await?;
 
                     self.check_cache.remove(&sighash);
                     self.dnfs.clear();
@@ -387,29 +656,75 @@ impl VoteChainRunner {
                     Ok::<_, anyhow::Error>(sighash)
                 };
 
-                result.send(res.await.map_err(|e| e.to_string())).unwrap();
+                // only the writes of accepted ballots stay in the block
+                let res = match res.await {
+                    res if legacy => res,
+                    Ok(sighash) => self.store.release_savepoint().await.map(|_| sighash),
+                    Err(e) => {
+                        let _ = self.store.rollback_to_savepoint().await;
+                        Err(e)
+                    }
+                };
+                let res = res.map_err(|e| e.to_string());
+                self.block_events.push(TxEvent {
+                    tx_hash: tx_hash.clone(),
+                    error: res.as_ref().err().cloned(),
+                });
+                result.send(res).unwrap();
             }
             Command::Commit(result) => {
-                let _ = sqlx::query("COMMIT").execute(&mut self.connection).await;
-
-                let app_state = Self::get_state(&mut self.connection).await?;
+                let app_state = self.store.app_state().await?;
                 let app_state = AppState {
                     height: app_state.height + 1,
                     ..app_state
                 };
-                store_prop(
-                    &mut self.connection,
-                    "state",
-                    &serde_json::to_string(&app_state).unwrap(),
-                ).await?;
+                self.store.store_app_state(&app_state).await?;
+                self.store.record_app_state(&app_state, self.block_time).await?;
+                self.store.commit().await?;
+                self.in_block = false;
 
                 result.send(app_state).unwrap();
+
+                for event in std::mem::take(&mut self.block_events) {
+                    // no subscribers is not an error
+                    let _ = self.events.send(event);
+                }
+                for (dir, result) in std::mem::take(&mut self.pending_backups) {
+                    let res = self.backup(&dir).await;
+                    let _ = result.send(res.map_err(|e| e.to_string()));
+                }
+            }
+            Command::Backup(dir, result) => {
+                if self.in_block {
+                    self.pending_backups.push((dir.clone(), result.clone()));
+                } else {
+                    let res = self.backup(dir).await;
+                    let _ = result.send(res.map_err(|e| e.to_string()));
+                }
             }
         }
 
         Ok(())
     }
 
+    async fn backup(&mut self, dir: &str) -> Result<BackupInfo> {
+        let height = self.store.app_state().await?.height;
+        backup(&mut self.store, &backup_path(dir, height)).await
+    }
+
+    pub fn spawn(self) -> JoinHandle<()>
+    where
+        S: Send + 'static,
+    {
+        std::thread::spawn(move || {
+            let r = Builder::new_current_thread().enable_all().build().unwrap();
+            r.block_on(async move {
+                let res = self.run().await;
+                println!("{:?}", res);
+            })
+        })
+    }
+
     pub async fn run(mut self) -> Result<()> {
         loop {
             let cmd = self.cmd_rx.recv().map_err(anyhow::Error::msg)?;
@@ -428,4 +743,21 @@ impl VoteChainRunner {
     }
 }
 
+/// Hash of a tx as shown by CometBFT
+pub fn tx_hash(tx: &[u8]) -> String {
+    hex::encode_upper(Sha256::digest(tx))
+}
+
+/// Hash of the latest cmx roots of every election, ordered by election id
+pub fn app_hash(cmx_roots: &[Vec<u8>]) -> Vec<u8> {
+    let mut hasher = Params::new()
+        .hash_length(32)
+        .personal(PERSO_VOTE_BFT)
+        .to_state();
+    for h in cmx_roots.iter() {
+        hasher.update(h);
+    }
+    hasher.finalize().as_bytes().to_vec()
+}
+
 const PERSO_VOTE_BFT: &[u8] = b"Zcash_Vote_CmBFT";
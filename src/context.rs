@@ -1,24 +1,42 @@
+use std::sync::Arc;
+
+use anyhow::Result;
 use sqlx::{sqlite::SqliteConnectOptions, SqlitePool};
 
+use crate::rpc::CometRpc;
+
 pub struct Context {
     pub data_path: String,
     pub db_path: String,
     pub comet_bft: u16,
+    pub backup_dir: Option<String>,
+    /// seconds to wait for a ballot to be committed
+    pub commit_timeout: u64,
+    /// queue ballots in the outbox with `wait=queue`
+    pub outbox: bool,
+    /// bearer token of the `/admin` endpoints, which are disabled without it
+    pub admin_token: Option<String>,
+    pub rpc: Arc<CometRpc>,
     pub pool: SqlitePool,
 }
 
 impl Context {
-    pub async fn new(data_path: String, db_path: String, comet_bft: u16) -> Self {
+    pub async fn new(data_path: String, db_path: String, comet_bft: u16) -> Result<Self> {
         let options = SqliteConnectOptions::new().filename(&db_path)
             .create_if_missing(true);
         let pool =
-            SqlitePool::connect_with(options).await.unwrap();
+            SqlitePool::connect_with(options).await?;
 
-        Self {
+        Ok(Self {
             data_path,
             db_path,
             comet_bft,
+            backup_dir: None,
+            commit_timeout: 60,
+            outbox: false,
+            admin_token: None,
+            rpc: Arc::new(CometRpc::new(comet_bft)),
             pool,
-        }
+        })
     }
 }
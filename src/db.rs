@@ -1,50 +1,71 @@
 use anyhow::Result;
 use blake2b_simd::Params;
-use orchard::vote::Ballot;
+use orchard::vote::{Ballot, Frontier, OrchardHash};
 use serde::{Deserialize, Serialize};
-use sqlx::SqliteConnection;
+use sqlx::{sqlite::SqliteRow, Row, SqliteConnection};
 use zcash_vote::{
+    as_byte256,
     db::{load_prop, store_cmx_root, store_prop},
     election::Election,
 };
 
-#[derive(Serialize, Deserialize, Debug)]
+use crate::witness::completed_nodes;
+
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
 pub struct AppState {
     pub height: u32,
     pub hash: String,
+    /// ids of the elections whose cmx roots make the hash, in hash order
+    #[serde(default)]
+    pub elections: Vec<String>,
 }
 
-pub async fn create_schema(connection: &mut SqliteConnection) -> Result<()> {
-    zcash_vote::db::create_schema(connection).await?;
-
-    sqlx::query(
-        "CREATE TABLE IF NOT EXISTS properties(
-        id_property INTEGER PRIMARY KEY,
-        name TEXT NOT NULL UNIQUE,
-        value TEXT NOT NULL)",
-    )
-    .execute(&mut *connection)
-    .await?;
+/// CometBFT block that included a ballot
+#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq)]
+pub struct BlockRef {
+    pub height: u32,
+    /// unix timestamp in seconds
+    pub time: i64,
+    /// sha256 of the tx, as shown by CometBFT
+    pub tx_hash: String,
+}
 
-    sqlx::query(
-        "CREATE TABLE IF NOT EXISTS elections(
-            id_election INTEGER PRIMARY KEY,
-            id TEXT NOT NULL UNIQUE,
-            definition TEXT NOT NULL,
-            closed BOOLEAN NOT NULL)",
-    )
-    .execute(&mut *connection)
-    .await?;
+impl BlockRef {
+    /// From the nullable block columns of `ballots`
+    pub fn from_columns(height: Option<u32>, time: Option<i64>, tx_hash: Option<String>) -> Option<Self> {
+        match (height, time, tx_hash) {
+            (Some(height), Some(time), Some(tx_hash)) => Some(BlockRef { height, time, tx_hash }),
+            _ => None,
+        }
+    }
+}
 
-    if load_prop(connection, "state").await?.is_none() {
+impl AppState {
+    pub fn initial() -> Self {
         let hash = Params::new()
             .hash_length(32)
             .personal(b"Zcash_Vote_CmBFT")
             .to_state()
             .finalize();
         let hash = hex::encode(hash.as_bytes());
+        AppState {
+            height: 0,
+            hash,
+            elections: vec![],
+        }
+    }
+}
+
+/// Apply the migrations of `migrations/` that have not run yet.
+/// sqlx records the schema version in `_sqlx_migrations`.
+pub async fn create_schema(connection: &mut SqliteConnection) -> Result<()> {
+    sqlx::migrate!().run(&mut *connection).await?;
+    migrate_binary(connection).await?;
+    index_nullifiers(connection).await?;
+    index_cmx_leaves(connection).await?;
 
-        let initial_state = AppState { height: 0, hash };
+    if load_prop(connection, "state").await?.is_none() {
+        let initial_state = AppState::initial();
         store_prop(
             connection,
             "state",
@@ -56,6 +77,37 @@ pub async fn create_schema(connection: &mut SqliteConnection) -> Result<()> {
     Ok(())
 }
 
+/// Fails if `create_schema` would change the database: a migration has
+/// not run or rows of older versions are not converted
+pub async fn check_schema(connection: &mut SqliteConnection) -> Result<()> {
+    let applied: Vec<(i64,)> = sqlx::query_as(
+        "SELECT version FROM _sqlx_migrations WHERE success")
+        .fetch_all(&mut *connection)
+        .await
+        .map_err(|_| anyhow::anyhow!("The database has no schema version"))?;
+    for migration in sqlx::migrate!().iter() {
+        if !applied.contains(&(migration.version,)) {
+            anyhow::bail!(
+                "Migration {} ({}) has not run: the database must be upgraded",
+                migration.version,
+                migration.description
+            );
+        }
+    }
+    let (outdated,): (bool,) = sqlx::query_as(
+        "SELECT EXISTS (SELECT 1 FROM ballots WHERE typeof(data) = 'text')
+        OR EXISTS (SELECT 1 FROM cmx_frontiers WHERE typeof(frontier) = 'text')
+        OR EXISTS (SELECT 1 FROM dnfs WHERE height IS NULL)
+        OR EXISTS (SELECT 1 FROM ballots b
+            WHERE NOT EXISTS (SELECT 1 FROM cmx_leaves l WHERE l.election = b.election))")
+        .fetch_one(&mut *connection)
+        .await?;
+    if outdated {
+        anyhow::bail!("Rows of an older version are not converted: the database must be upgraded");
+    }
+    Ok(())
+}
+
 pub async fn get_election(
     connection: &mut SqliteConnection,
     id: &str,
@@ -68,6 +120,34 @@ pub async fn get_election(
     Ok(res)
 }
 
+/// id_election, id, definition and closed flag, ordered by id
+pub async fn list_elections(
+    connection: &mut SqliteConnection,
+    closed: Option<bool>,
+    offset: u32,
+    limit: u32,
+) -> Result<Vec<(u32, String, String, bool)>> {
+    let res = sqlx::query_as(
+        "SELECT id_election, id, definition, closed FROM elections
+        WHERE ?1 IS NULL OR closed = ?1
+        ORDER BY id LIMIT ?2 OFFSET ?3")
+        .bind(closed)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&mut *connection)
+        .await?;
+    Ok(res)
+}
+
+pub async fn count_elections(connection: &mut SqliteConnection, closed: Option<bool>) -> Result<u32> {
+    let (n,): (u32,) = sqlx::query_as(
+        "SELECT COUNT(*) FROM elections WHERE ?1 IS NULL OR closed = ?1")
+        .bind(closed)
+        .fetch_one(&mut *connection)
+        .await?;
+    Ok(n)
+}
+
 pub async fn store_election(
     connection: &mut SqliteConnection,
     election: &Election,
@@ -89,6 +169,30 @@ pub async fn store_election(
     Ok(id_election)
 }
 
+pub async fn init_election(connection: &mut SqliteConnection, election: &Election) -> Result<u32> {
+    let id_election = store_election(connection, election, false).await?;
+    let cmx_frontier = election.cmx_frontier.as_ref().unwrap();
+    let cmx_root = cmx_frontier.root();
+    let frontier = encode_frontier(cmx_frontier)?;
+    sqlx::query(
+        "INSERT INTO cmx_frontiers(election, height, frontier)
+        VALUES (?1, 0, ?2) ON CONFLICT DO NOTHING",
+    )
+    .bind(id_election)
+    .bind(&frontier)
+    .execute(&mut *connection)
+    .await?;
+    sqlx::query(
+        "INSERT INTO cmx_roots(election, height, hash)
+        VALUES (?1, 0, ?2) ON CONFLICT DO NOTHING",
+    )
+    .bind(id_election)
+    .bind(&cmx_root[..])
+    .execute(&mut *connection)
+    .await?;
+    Ok(id_election)
+}
+
 pub async fn check_cmx_root(
     connection: &mut SqliteConnection,
     id_election: u32,
@@ -109,34 +213,221 @@ pub async fn store_ballot(
     height: u32,
     ballot: &Ballot,
     cmx_root: &[u8],
+    block: Option<&BlockRef>,
 ) -> Result<u32> {
     let hash = ballot.data.sighash()?;
     let r = sqlx::query(
         "INSERT INTO ballots
-        (election, height, hash, data)
-        VALUES (?1, ?2, ?3, ?4)",
+        (election, height, hash, data, block_height, block_time, tx_hash)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
     )
     .bind(id_election)
     .bind(height)
     .bind(&hash)
-    .bind(serde_json::to_string(ballot)?)
+    .bind(encode_ballot(ballot)?)
+    .bind(block.map(|b| b.height))
+    .bind(block.map(|b| b.time))
+    .bind(block.map(|b| b.tx_hash.clone()))
     .execute(&mut *connection)
     .await?;
     let id_ballot = r.last_insert_rowid() as u32;
 
     store_cmx_root(connection, id_election, id_ballot, cmx_root).await?;
+    store_cmx_leaves(connection, id_election, height, ballot).await?;
     Ok(id_ballot)
 }
 
+/// Append the outputs of the ballot at `height` to the cmx leaves
+/// and store the tree nodes that they complete
+pub async fn store_cmx_leaves(
+    connection: &mut SqliteConnection,
+    id_election: u32,
+    height: u32,
+    ballot: &Ballot,
+) -> Result<()> {
+    let (frontier, ): (Vec<u8>, ) = sqlx::query_as(
+        "SELECT frontier FROM cmx_frontiers WHERE election = ?1 AND height = ?2")
+        .bind(id_election)
+        .bind(height - 1)
+        .fetch_one(&mut *connection).await?;
+    let mut frontier = decode_frontier(&frontier)?;
+    if height == 1 {
+        store_cmx_nodes(connection, id_election, 0, &frontier).await?;
+    }
+    for action in ballot.data.actions.iter() {
+        sqlx::query(
+            "INSERT INTO cmx_leaves(election, idx, height, cmx)
+            SELECT ?1, COALESCE(MAX(idx) + 1, 0), ?2, ?3 FROM cmx_leaves WHERE election = ?1")
+            .bind(id_election)
+            .bind(height)
+            .bind(&action.cmx)
+            .execute(&mut *connection)
+            .await?;
+        frontier.append(OrchardHash(as_byte256(&action.cmx)));
+        store_cmx_nodes(connection, id_election, height, &frontier).await?;
+    }
+    Ok(())
+}
+
+/// Store the tree nodes completed by the last leaf of `frontier`
+async fn store_cmx_nodes(
+    connection: &mut SqliteConnection,
+    id_election: u32,
+    height: u32,
+    frontier: &Frontier,
+) -> Result<()> {
+    for (level, idx, hash) in completed_nodes(frontier)? {
+        sqlx::query(
+            "INSERT INTO cmx_nodes(election, level, idx, height, hash)
+            VALUES (?1, ?2, ?3, ?4, ?5) ON CONFLICT DO NOTHING")
+            .bind(id_election)
+            .bind(level)
+            .bind(idx as i64)
+            .bind(height)
+            .bind(&hash[..])
+            .execute(&mut *connection)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Index of `cmx` in the cmx leaves up to the ballot `height`
+pub async fn get_cmx_leaf_index(
+    connection: &mut SqliteConnection,
+    id_election: u32,
+    cmx: &[u8],
+    height: u32,
+) -> Result<Option<u32>> {
+    let r: Option<(u32,)> = sqlx::query_as(
+        "SELECT idx FROM cmx_leaves WHERE election = ?1 AND cmx = ?2 AND height <= ?3
+        ORDER BY idx LIMIT 1")
+        .bind(id_election)
+        .bind(cmx)
+        .bind(height)
+        .fetch_optional(&mut *connection)
+        .await?;
+    Ok(r.map(|(idx,)| idx))
+}
+
+/// cmx leaf at `idx`
+pub async fn get_cmx_leaf(connection: &mut SqliteConnection, id_election: u32, idx: u32) -> Result<Vec<u8>> {
+    let (cmx, ): (Vec<u8>, ) = sqlx::query_as(
+        "SELECT cmx FROM cmx_leaves WHERE election = ?1 AND idx = ?2")
+        .bind(id_election)
+        .bind(idx)
+        .fetch_one(&mut *connection).await?;
+    Ok(cmx)
+}
+
+/// Complete cmx tree node at `level` and `idx`
+pub async fn get_cmx_node(connection: &mut SqliteConnection, id_election: u32, level: u8, idx: u64) -> Result<Vec<u8>> {
+    let (hash, ): (Vec<u8>, ) = sqlx::query_as(
+        "SELECT hash FROM cmx_nodes WHERE election = ?1 AND level = ?2 AND idx = ?3")
+        .bind(id_election)
+        .bind(level)
+        .bind(idx as i64)
+        .fetch_one(&mut *connection).await?;
+    Ok(hash)
+}
+
 pub async fn get_ballot_height(
     connection: &mut SqliteConnection,
     id_election: u32,
     height: u32,
-) -> Result<String> {
-    let (e, ): (String, ) = sqlx::query_as(
+) -> Result<Ballot> {
+    let (e, ): (Vec<u8>, ) = sqlx::query_as(
         "SELECT data FROM ballots WHERE election = ?1 AND height = ?2")
         .bind(id_election).bind(height).fetch_one(&mut *connection).await?;
-    Ok(e)
+    decode_ballot(&e)
+}
+
+pub async fn get_ballot_block(
+    connection: &mut SqliteConnection,
+    id_election: u32,
+    height: u32,
+) -> Result<Option<BlockRef>> {
+    let (block_height, time, tx_hash): (Option<u32>, Option<i64>, Option<String>) = sqlx::query_as(
+        "SELECT block_height, block_time, tx_hash FROM ballots WHERE election = ?1 AND height = ?2")
+        .bind(id_election).bind(height).fetch_one(&mut *connection).await?;
+    Ok(BlockRef::from_columns(block_height, time, tx_hash))
+}
+
+/// Ballots with `from <= height <= to`, at most `limit`, in height order
+pub async fn get_ballot_range(
+    connection: &mut SqliteConnection,
+    id_election: u32,
+    from: u32,
+    to: u32,
+    limit: u32,
+) -> Result<Vec<(u32, Ballot, Option<BlockRef>)>> {
+    let ballots = sqlx::query(
+        "SELECT height, data, block_height, block_time, tx_hash FROM ballots
+        WHERE election = ?1 AND height >= ?2 AND height <= ?3
+        ORDER BY height LIMIT ?4")
+        .bind(id_election)
+        .bind(from)
+        .bind(to)
+        .bind(limit)
+        .map(ballot_row)
+        .fetch_all(&mut *connection)
+        .await?;
+    ballots.into_iter().collect()
+}
+
+pub async fn get_ballot_by_sighash(
+    connection: &mut SqliteConnection,
+    id_election: u32,
+    sighash: &[u8],
+) -> Result<Option<(u32, Ballot, Option<BlockRef>)>> {
+    let ballot = sqlx::query(
+        "SELECT height, data, block_height, block_time, tx_hash FROM ballots
+        WHERE election = ?1 AND hash = ?2")
+        .bind(id_election)
+        .bind(sighash)
+        .map(ballot_row)
+        .fetch_optional(&mut *connection)
+        .await?;
+    ballot.transpose()
+}
+
+/// Election id, ballot height and block height of a committed tx
+pub async fn get_ballot_by_tx_hash(
+    connection: &mut SqliteConnection,
+    tx_hash: &str,
+) -> Result<Option<(String, u32, u32)>> {
+    let r = sqlx::query_as(
+        "SELECT e.id, b.height, b.block_height FROM ballots b
+        JOIN elections e ON e.id_election = b.election
+        WHERE b.tx_hash = ?1")
+        .bind(tx_hash)
+        .fetch_optional(&mut *connection)
+        .await?;
+    Ok(r)
+}
+
+/// Decode a row of `height, data, block_height, block_time, tx_hash`
+pub fn ballot_row(r: SqliteRow) -> Result<(u32, Ballot, Option<BlockRef>)> {
+    let height: u32 = r.get(0);
+    let data: Vec<u8> = r.get(1);
+    let block = BlockRef::from_columns(r.get(2), r.get(3), r.get(4));
+    Ok((height, decode_ballot(&data)?, block))
+}
+
+/// Height and sighash of the ballot that spent a nullifier
+pub async fn get_nullifier(
+    connection: &mut SqliteConnection,
+    id_election: u32,
+    nf: &[u8],
+) -> Result<Option<(u32, Vec<u8>)>> {
+    let r: Option<(u32, Vec<u8>)> = sqlx::query_as(
+        "SELECT d.height, b.hash FROM dnfs d
+        JOIN ballots b ON b.election = d.election AND b.height = d.height
+        WHERE d.election = ?1 AND d.hash = ?2")
+        .bind(id_election)
+        .bind(nf)
+        .fetch_optional(&mut *connection)
+        .await?;
+    Ok(r)
 }
 
 pub async fn get_num_ballots(connection: &mut SqliteConnection, id_election: u32) -> Result<u32> {
@@ -146,3 +437,146 @@ pub async fn get_num_ballots(connection: &mut SqliteConnection, id_election: u32
         .fetch_one(&mut *connection).await?;
     Ok(n)
 }
+
+pub async fn get_cmx_roots(connection: &mut SqliteConnection, id_election: u32) -> Result<Vec<Vec<u8>>> {
+    let roots: Vec<(Vec<u8>, )> = sqlx::query_as(
+        "SELECT hash FROM cmx_roots WHERE election = ?1 ORDER BY height")
+        .bind(id_election)
+        .fetch_all(&mut *connection).await?;
+    Ok(roots.into_iter().map(|(r, )| r).collect())
+}
+
+/// cmx root after the ballot `height` of the election
+pub async fn get_cmx_root_at(connection: &mut SqliteConnection, id_election: u32, height: u32) -> Result<Vec<u8>> {
+    // cmx roots are indexed by id_ballot, the initial root by 0
+    let (root, ): (Vec<u8>, ) = sqlx::query_as(
+        "SELECT hash FROM cmx_roots WHERE election = ?1 AND height =
+        COALESCE((SELECT id_ballot FROM ballots WHERE election = ?1 AND height = ?2), 0)")
+        .bind(id_election)
+        .bind(height)
+        .fetch_one(&mut *connection).await?;
+    Ok(root)
+}
+
+pub async fn get_latest_cmx_root(connection: &mut SqliteConnection, id_election: u32) -> Result<Vec<u8>> {
+    let (root, ): (Vec<u8>, ) = sqlx::query_as(
+        "SELECT hash FROM cmx_roots WHERE election = ?1 ORDER BY height DESC LIMIT 1")
+        .bind(id_election)
+        .fetch_one(&mut *connection).await?;
+    Ok(root)
+}
+
+// Ballots and frontiers are stored with bincode.
+// The REST API transcodes them to JSON.
+
+pub fn encode_ballot(ballot: &Ballot) -> Result<Vec<u8>> {
+    Ok(bincode::serialize(ballot)?)
+}
+
+pub fn decode_ballot(data: &[u8]) -> Result<Ballot> {
+    Ok(bincode::deserialize(data)?)
+}
+
+pub fn encode_frontier(frontier: &Frontier) -> Result<Vec<u8>> {
+    Ok(bincode::serialize(frontier)?)
+}
+
+pub fn decode_frontier(data: &[u8]) -> Result<Frontier> {
+    Ok(bincode::deserialize(data)?)
+}
+
+/// Convert the ballots and frontiers that are still stored as JSON text
+async fn migrate_binary(connection: &mut SqliteConnection) -> Result<()> {
+    let ballots = sqlx::query(
+        "SELECT id_ballot, data FROM ballots WHERE typeof(data) = 'text'")
+        .map(|r: SqliteRow| {
+            let id_ballot: u32 = r.get(0);
+            let data: String = r.get(1);
+            (id_ballot, data)
+        })
+        .fetch_all(&mut *connection).await?;
+    for (id_ballot, data) in ballots.iter() {
+        let ballot = serde_json::from_str::<Ballot>(data)?;
+        sqlx::query("UPDATE ballots SET data = ?2 WHERE id_ballot = ?1")
+            .bind(id_ballot)
+            .bind(encode_ballot(&ballot)?)
+            .execute(&mut *connection).await?;
+    }
+
+    let frontiers = sqlx::query(
+        "SELECT id_cmx_frontier, frontier FROM cmx_frontiers WHERE typeof(frontier) = 'text'")
+        .map(|r: SqliteRow| {
+            let id_frontier: u32 = r.get(0);
+            let frontier: String = r.get(1);
+            (id_frontier, frontier)
+        })
+        .fetch_all(&mut *connection).await?;
+    for (id_frontier, frontier) in frontiers.iter() {
+        let frontier = serde_json::from_str::<Frontier>(frontier)?;
+        sqlx::query("UPDATE cmx_frontiers SET frontier = ?2 WHERE id_cmx_frontier = ?1")
+            .bind(id_frontier)
+            .bind(encode_frontier(&frontier)?)
+            .execute(&mut *connection).await?;
+    }
+
+    if !ballots.is_empty() || !frontiers.is_empty() {
+        tracing::info!("Converted {} ballots and {} frontiers to binary", ballots.len(), frontiers.len());
+    }
+    Ok(())
+}
+
+/// Fill the ballot height of the nullifiers stored without one
+pub async fn index_nullifiers(connection: &mut SqliteConnection) -> Result<()> {
+    let elections: Vec<(u32,)> = sqlx::query_as(
+        "SELECT DISTINCT election FROM dnfs WHERE height IS NULL")
+        .fetch_all(&mut *connection).await?;
+    for (id_election,) in elections.iter() {
+        let ballots = sqlx::query(
+            "SELECT height, data FROM ballots WHERE election = ?1")
+            .bind(id_election)
+            .map(|r: SqliteRow| {
+                let height: u32 = r.get(0);
+                let data: Vec<u8> = r.get(1);
+                (height, data)
+            })
+            .fetch_all(&mut *connection).await?;
+        for (height, data) in ballots.iter() {
+            let ballot = decode_ballot(data)?;
+            for action in ballot.data.actions.iter() {
+                sqlx::query("UPDATE dnfs SET height = ?3 WHERE election = ?1 AND hash = ?2")
+                    .bind(id_election)
+                    .bind(&action.nf)
+                    .bind(height)
+                    .execute(&mut *connection).await?;
+            }
+        }
+        tracing::info!("Indexed the nullifiers of {} ballots", ballots.len());
+    }
+    Ok(())
+}
+
+/// Fill the cmx leaves and nodes of the elections whose ballots were
+/// stored by versions without `cmx_leaves` or `cmx_nodes`
+pub async fn index_cmx_leaves(connection: &mut SqliteConnection) -> Result<()> {
+    let elections: Vec<(u32,)> = sqlx::query_as(
+        "SELECT DISTINCT election FROM ballots b
+        WHERE NOT EXISTS (SELECT 1 FROM cmx_leaves l WHERE l.election = b.election)")
+        .fetch_all(&mut *connection).await?;
+    for (id_election,) in elections.iter() {
+        let ballots = sqlx::query(
+            "SELECT height, data FROM ballots WHERE election = ?1 ORDER BY height")
+            .bind(id_election)
+            .map(|r: SqliteRow| {
+                let height: u32 = r.get(0);
+                let data: Vec<u8> = r.get(1);
+                (height, data)
+            })
+            .fetch_all(&mut *connection).await?;
+        for (height, data) in ballots.iter() {
+            let ballot = decode_ballot(data)?;
+            store_cmx_leaves(connection, *id_election, *height, &ballot).await?;
+        }
+        tracing::info!("Indexed the cmx leaves of {} ballots", ballots.len());
+    }
+    Ok(())
+}
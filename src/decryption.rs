@@ -0,0 +1,195 @@
+use anyhow::Result;
+use blake2b_simd::Params;
+use chacha20::{
+    cipher::{KeyIvInit, StreamCipher, StreamCipherSeek},
+    ChaCha20,
+};
+use ff::{FromUniformBytes, PrimeField};
+use group::{Group, GroupEncoding};
+use orchard::{
+    keys::IncomingViewingKey,
+    note::{ExtractedNoteCommitment, Nullifier, RandomSeed},
+    value::NoteValue,
+    vote::BallotAction,
+    Address, Note,
+};
+use pasta_curves::{arithmetic::CurveExt, pallas};
+use serde::{Deserialize, Serialize};
+use zcash_note_encryption::COMPACT_NOTE_SIZE;
+use zcash_vote::as_byte256;
+
+/// Chaum-Pedersen proof that `shared_secret = [ivk] epk` where
+/// `pk_d = [ivk] g_d` is the candidate address. Anyone can decrypt
+/// the output with the shared secret without learning `ivk`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct DecryptionProof {
+    pub shared_secret: String,
+    pub challenge: String,
+    pub response: String,
+}
+
+pub fn ivk_scalar(ivk: &IncomingViewingKey) -> Result<pallas::Scalar> {
+    let ivk = ivk.to_bytes();
+    let ivk: Option<pallas::Scalar> = pallas::Scalar::from_repr(as_byte256(&ivk[32..])).into();
+    ivk.ok_or(anyhow::anyhow!("Invalid incoming viewing key"))
+}
+
+pub fn prove(ivk: &pallas::Scalar, address: &[u8; 43], epk: &[u8]) -> Result<DecryptionProof> {
+    let g_d = diversify_hash(&address[0..11]);
+    let pk_d = g_d * ivk;
+    let epk = parse_point(epk)?;
+    let shared_secret = epk * ivk;
+
+    let k = {
+        let mut nonce = nonce_hasher();
+        nonce.update(&ivk.to_repr());
+        nonce.update(&epk.to_bytes());
+        nonce.update(&address[..]);
+        pallas::Scalar::from_uniform_bytes(nonce.finalize().as_array())
+    };
+    let c = challenge(&g_d, &pk_d, &epk, &shared_secret, &(g_d * k), &(epk * k));
+    let s = k + c * ivk;
+
+    Ok(DecryptionProof {
+        shared_secret: hex::encode(shared_secret.to_bytes()),
+        challenge: hex::encode(c.to_repr()),
+        response: hex::encode(s.to_repr()),
+    })
+}
+
+/// Check a decryption proof against a ballot output and
+/// return the value of the note sent to `address`.
+/// The decrypted note must be the note committed by the output.
+pub fn verify(proof: &DecryptionProof, address: &[u8; 43], action: &BallotAction) -> Result<u64> {
+    let g_d = diversify_hash(&address[0..11]);
+    let pk_d = parse_point(&address[11..43])?;
+    let epk = parse_point(&action.epk)?;
+    let shared_secret = parse_point(&hex::decode(&proof.shared_secret)?)?;
+    let c = parse_scalar(&proof.challenge)?;
+    let s = parse_scalar(&proof.response)?;
+
+    let a = g_d * s - pk_d * c;
+    let b = epk * s - shared_secret * c;
+    if challenge(&g_d, &pk_d, &epk, &shared_secret, &a, &b) != c {
+        anyhow::bail!("Invalid decryption proof");
+    }
+
+    if action.enc.len() < COMPACT_NOTE_SIZE {
+        anyhow::bail!("Invalid note ciphertext");
+    }
+    let key = Params::new()
+        .hash_length(32)
+        .personal(PERSO_ORCHARD_KDF)
+        .to_state()
+        .update(&shared_secret.to_bytes())
+        .update(&action.epk)
+        .finalize();
+    let mut plaintext = [0u8; COMPACT_NOTE_SIZE];
+    plaintext.copy_from_slice(&action.enc[0..COMPACT_NOTE_SIZE]);
+    let mut keystream = ChaCha20::new(key.as_bytes().into(), [0u8; 12][..].into());
+    keystream.seek(64u32);
+    keystream.apply_keystream(&mut plaintext);
+
+    if plaintext[0] != 0x02 {
+        anyhow::bail!("Invalid note plaintext");
+    }
+    if plaintext[1..12] != address[0..11] {
+        anyhow::bail!("Note was not sent to this address");
+    }
+    let value = u64::from_le_bytes(plaintext[12..20].try_into().unwrap());
+    let rseed: [u8; 32] = plaintext[20..52].try_into().unwrap();
+    check_note(&g_d, address, value, &rseed, action)?;
+    Ok(value)
+}
+
+/// Rebuild the note from its plaintext and check its ephemeral key
+/// and its commitment against the output. The ballot proof only binds
+/// `cmx`, not the ciphertext.
+fn check_note(
+    g_d: &pallas::Point,
+    address: &[u8; 43],
+    value: u64,
+    rseed: &[u8; 32],
+    action: &BallotAction,
+) -> Result<()> {
+    // the nullifier of the action is the rho of its output note
+    let esk = {
+        let mut prf = Params::new().hash_length(64).personal(PERSO_EXPAND_SEED).to_state();
+        prf.update(rseed);
+        prf.update(&[4]);
+        prf.update(&action.nf);
+        pallas::Scalar::from_uniform_bytes(prf.finalize().as_array())
+    };
+    if (g_d * esk).to_bytes()[..] != action.epk[..] {
+        anyhow::bail!("Ephemeral key does not match the note");
+    }
+
+    let rho: Option<Nullifier> = Nullifier::from_bytes(&as_byte256(&action.nf)).into();
+    let rho = rho.ok_or(anyhow::anyhow!("Invalid nullifier"))?;
+    let recipient: Option<Address> = Address::from_raw_address_bytes(address).into();
+    let rseed: Option<RandomSeed> = RandomSeed::from_bytes(*rseed, &rho).into();
+    let note: Option<Note> = match (recipient, rseed) {
+        (Some(recipient), Some(rseed)) => {
+            Note::from_parts(recipient, NoteValue::from_raw(value), rho, rseed).into()
+        }
+        _ => None,
+    };
+    let note = note.ok_or(anyhow::anyhow!("Invalid note plaintext"))?;
+    let cmx = ExtractedNoteCommitment::from(note.commitment());
+    if cmx.to_bytes()[..] != action.cmx[..] {
+        anyhow::bail!("Note commitment does not match cmx");
+    }
+    Ok(())
+}
+
+fn diversify_hash(d: &[u8]) -> pallas::Point {
+    let hasher = pallas::Point::hash_to_curve(PERSO_ORCHARD_GD);
+    let g_d = hasher(d);
+    if bool::from(g_d.is_identity()) {
+        hasher(&[])
+    } else {
+        g_d
+    }
+}
+
+fn challenge(
+    g_d: &pallas::Point,
+    pk_d: &pallas::Point,
+    epk: &pallas::Point,
+    shared_secret: &pallas::Point,
+    a: &pallas::Point,
+    b: &pallas::Point,
+) -> pallas::Scalar {
+    let mut hasher = Params::new().hash_length(64).personal(PERSO_DLEQ).to_state();
+    for p in [g_d, pk_d, epk, shared_secret, a, b] {
+        hasher.update(&p.to_bytes());
+    }
+    pallas::Scalar::from_uniform_bytes(hasher.finalize().as_array())
+}
+
+fn nonce_hasher() -> blake2b_simd::State {
+    Params::new().hash_length(64).personal(PERSO_NONCE).to_state()
+}
+
+fn parse_point(p: &[u8]) -> Result<pallas::Point> {
+    if p.len() != 32 {
+        anyhow::bail!("Invalid point");
+    }
+    let p: Option<pallas::Point> = pallas::Point::from_bytes(&as_byte256(p)).into();
+    p.ok_or(anyhow::anyhow!("Invalid point"))
+}
+
+fn parse_scalar(s: &str) -> Result<pallas::Scalar> {
+    let s = hex::decode(s)?;
+    if s.len() != 32 {
+        anyhow::bail!("Invalid scalar");
+    }
+    let s: Option<pallas::Scalar> = pallas::Scalar::from_repr(as_byte256(&s)).into();
+    s.ok_or(anyhow::anyhow!("Invalid scalar"))
+}
+
+const PERSO_ORCHARD_GD: &str = "z.cash:Orchard-gd";
+const PERSO_ORCHARD_KDF: &[u8] = b"Zcash_OrchardKDF";
+const PERSO_EXPAND_SEED: &[u8] = b"Zcash_ExpandSeed";
+const PERSO_DLEQ: &[u8] = b"Zcash_Vote_DLEQ_";
+const PERSO_NONCE: &[u8] = b"Zcash_Vote_Nonce";
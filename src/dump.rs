@@ -0,0 +1,178 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{sqlite::SqliteRow, Row, SqliteConnection};
+
+use crate::{db::BlockRef, store::VoteStore};
+
+/// Consensus state at a block height, without local ids such as
+/// `id_election` or `id_ballot`, so that dumps of different nodes compare
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StateDump {
+    pub height: u32,
+    pub app_hash: String,
+    pub elections: Vec<ElectionDump>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ElectionDump {
+    pub id: String,
+    pub name: String,
+    /// current value, it is not recorded per height
+    pub closed: bool,
+    pub ballots: Vec<BallotDump>,
+    pub nullifiers: Vec<String>,
+    /// bincode of the frontier at every ballot height
+    pub frontiers: Vec<String>,
+    pub roots: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BallotDump {
+    pub height: u32,
+    pub sighash: String,
+    pub block: Option<BlockRef>,
+}
+
+/// Dump the state at block `height`, or the current state
+pub async fn dump_state(connection: &mut SqliteConnection, height: Option<u32>) -> Result<StateDump> {
+    let current = connection.app_state().await?;
+    let (height, app_hash, last_ballot) = match height {
+        Some(height) if height != current.height => {
+            let (hash, last_ballot): (String, u32) = sqlx::query_as(
+                "SELECT hash, last_ballot FROM app_states WHERE height = ?1")
+                .bind(height)
+                .fetch_optional(&mut *connection)
+                .await?
+                .ok_or(anyhow::anyhow!("No app state recorded at height {height}"))?;
+            (height, hash, Some(last_ballot))
+        }
+        _ => (current.height, current.hash, None),
+    };
+    let last_ballot = last_ballot.unwrap_or(u32::MAX);
+
+    let elections = sqlx::query(
+        "SELECT id_election, id, definition, closed FROM elections ORDER BY id")
+        .map(|r: SqliteRow| {
+            let id_election: u32 = r.get(0);
+            let id: String = r.get(1);
+            let definition: String = r.get(2);
+            let closed: bool = r.get(3);
+            (id_election, id, definition, closed)
+        })
+        .fetch_all(&mut *connection)
+        .await?;
+
+    let mut dumps = vec![];
+    for (id_election, id, definition, closed) in elections {
+        let name = serde_json::from_str::<Value>(&definition)?
+            .get("name")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string();
+
+        let ballots = sqlx::query(
+            "SELECT height, hash, block_height, block_time, tx_hash
+            FROM ballots WHERE election = ?1 AND id_ballot <= ?2 ORDER BY height")
+            .bind(id_election)
+            .bind(last_ballot)
+            .map(|r: SqliteRow| {
+                let height: u32 = r.get(0);
+                let hash: Vec<u8> = r.get(1);
+                let block = BlockRef::from_columns(r.get(2), r.get(3), r.get(4));
+                (height, hash, block)
+            })
+            .fetch_all(&mut *connection)
+            .await?;
+
+        // nullifiers are indexed by the ballot height in the election
+        let nfs: Vec<(Vec<u8>,)> = sqlx::query_as(
+            "SELECT hash FROM dnfs WHERE election = ?1 AND height <= ?2")
+            .bind(id_election)
+            .bind(ballots.len() as u32)
+            .fetch_all(&mut *connection)
+            .await?;
+        let mut nullifiers = nfs.into_iter().map(|(nf,)| hex::encode(nf)).collect::<Vec<_>>();
+        nullifiers.sort();
+
+        let frontiers: Vec<(Vec<u8>,)> = sqlx::query_as(
+            "SELECT frontier FROM cmx_frontiers WHERE election = ?1 AND height <= ?2 ORDER BY height")
+            .bind(id_election)
+            .bind(ballots.len() as u32)
+            .fetch_all(&mut *connection)
+            .await?;
+        let roots: Vec<(Vec<u8>,)> = sqlx::query_as(
+            "SELECT hash FROM cmx_roots WHERE election = ?1 AND height <= ?2 ORDER BY height")
+            .bind(id_election)
+            .bind(last_ballot)
+            .fetch_all(&mut *connection)
+            .await?;
+
+        dumps.push(ElectionDump {
+            id,
+            name,
+            closed,
+            ballots: ballots
+                .into_iter()
+                .map(|(height, hash, block)| BallotDump {
+                    height,
+                    sighash: hex::encode(hash),
+                    block,
+                })
+                .collect(),
+            nullifiers,
+            frontiers: frontiers.into_iter().map(|(f,)| hex::encode(f)).collect(),
+            roots: roots.into_iter().map(|(r,)| hex::encode(r)).collect(),
+        });
+    }
+
+    Ok(StateDump {
+        height,
+        app_hash,
+        elections: dumps,
+    })
+}
+
+/// Path and values of the first difference between two dumps.
+/// Elections are compared first because the app hash only
+/// reflects their differences.
+pub fn diff(a: &Value, b: &Value) -> Option<String> {
+    diff_at(".elections", &a["elections"], &b["elections"]).or_else(|| diff_at("", a, b))
+}
+
+fn diff_at(path: &str, a: &Value, b: &Value) -> Option<String> {
+    match (a, b) {
+        (Value::Object(ma), Value::Object(mb)) => {
+            // keys are sorted
+            for (k, va) in ma.iter() {
+                let p = format!("{path}.{k}");
+                match mb.get(k) {
+                    Some(vb) => {
+                        if let Some(d) = diff_at(&p, va, vb) {
+                            return Some(d);
+                        }
+                    }
+                    None => return Some(format!("{p}: {va} != (missing)")),
+                }
+            }
+            mb.iter()
+                .find(|(k, _)| !ma.contains_key(*k))
+                .map(|(k, vb)| format!("{path}.{k}: (missing) != {vb}"))
+        }
+        (Value::Array(va), Value::Array(vb)) => {
+            for (i, (ea, eb)) in va.iter().zip(vb.iter()).enumerate() {
+                if let Some(d) = diff_at(&format!("{path}[{i}]"), ea, eb) {
+                    return Some(d);
+                }
+            }
+            if va.len() != vb.len() {
+                let i = va.len().min(vb.len());
+                let show = |v: &Vec<Value>| v.get(i).map_or("(missing)".to_string(), Value::to_string);
+                return Some(format!("{path}[{i}]: {} != {}", show(va), show(vb)));
+            }
+            None
+        }
+        _ if a != b => Some(format!("{path}: {a} != {b}")),
+        _ => None,
+    }
+}
@@ -4,8 +4,11 @@ use std::{
 };
 
 use anyhow::Result;
+use sqlx::SqliteConnection;
 use zcash_vote::election::Election;
 
+use crate::db::init_election;
+
 pub fn scan_data_dir(data_dir: &str) -> Result<Vec<Election>> {
     let mut elections = vec![];
     let dir = read_dir(data_dir)?.flatten(); // Simplify the iterator
@@ -23,4 +26,18 @@ pub fn scan_data_dir(data_dir: &str) -> Result<Vec<Election>> {
     }
 
     Ok(elections)
-}
\ No newline at end of file
+}
+
+/// Store the elections of the data directory. Elections that are
+/// no longer there are closed.
+pub async fn load_elections(connection: &mut SqliteConnection, data_dir: &str) -> Result<Vec<Election>> {
+    let elections = scan_data_dir(data_dir)?;
+    tracing::info!("# elections = {}", elections.len());
+    sqlx::query("UPDATE elections SET closed = TRUE")
+        .execute(&mut *connection)
+        .await?;
+    for e in elections.iter() {
+        init_election(connection, e).await?;
+    }
+    Ok(elections)
+}
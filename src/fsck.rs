@@ -0,0 +1,282 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Result;
+use orchard::vote::OrchardHash;
+use sqlx::{sqlite::SqliteRow, Row, SqliteConnection};
+use zcash_vote::{as_byte256, db::load_prop};
+
+use crate::{
+    chain::app_hash,
+    db::{decode_ballot, decode_frontier, encode_frontier, AppState},
+    witness::completed_nodes,
+};
+
+/// Check the consistency of every election, then recompute the app hash.
+/// Returns every discrepancy found.
+pub async fn fsck(connection: &mut SqliteConnection) -> Result<Vec<String>> {
+    let mut errors = vec![];
+    let elections = sqlx::query("SELECT id_election, id FROM elections ORDER BY id")
+        .map(|r: SqliteRow| {
+            let id_election: u32 = r.get(0);
+            let id: String = r.get(1);
+            (id_election, id)
+        })
+        .fetch_all(&mut *connection)
+        .await?;
+
+    let mut election_roots = HashMap::new();
+    for (id_election, id) in elections.iter() {
+        tracing::info!("Checking election {id}");
+        let check = check_election(connection, *id_election).await?;
+        errors.extend(check.errors.into_iter().map(|e| format!("Election {id}: {e}")));
+        election_roots.insert(id.clone(), check.roots);
+    }
+
+    let app_state = load_prop(connection, "state").await?
+        .ok_or(anyhow::anyhow!("Missing app state"))?;
+    let app_state = serde_json::from_str::<AppState>(&app_state)?;
+    // the app hash was last computed after this ballot, from the roots
+    // of these elections
+    let recorded: Option<(u32, Option<String>)> = sqlx::query_as(
+        "SELECT last_ballot, elections FROM app_states WHERE height = ?1")
+        .bind(app_state.height)
+        .fetch_optional(&mut *connection)
+        .await?;
+    let (last_ballot, hashed_elections) = match recorded {
+        Some((last_ballot, Some(elections))) => (last_ballot, serde_json::from_str(&elections)?),
+        Some((last_ballot, None)) => (last_ballot, app_state.elections.clone()),
+        None if app_state.height == 0 => (0, vec![]),
+        // databases from before the app state history
+        None => {
+            let (last_ballot,): (u32,) = sqlx::query_as("SELECT COALESCE(MAX(id_ballot), 0) FROM ballots")
+                .fetch_one(&mut *connection)
+                .await?;
+            (last_ballot, app_state.elections.clone())
+        }
+    };
+    if last_ballot != 0 && hashed_elections.is_empty() {
+        errors.push(format!(
+            "App hash {} was computed by an older version that did not record its elections",
+            app_state.hash
+        ));
+        return Ok(errors);
+    }
+    match expected_app_hash(&election_roots, &hashed_elections, last_ballot) {
+        Ok(hash) if hash == app_state.hash => {}
+        Ok(_) => errors.push(format!(
+            "App hash {} does not match the cmx roots after ballot {last_ballot}",
+            app_state.hash
+        )),
+        Err(e) => errors.push(format!("App hash {}: {e}", app_state.hash)),
+    }
+
+    Ok(errors)
+}
+
+/// App hash of the cmx roots of `elections` after the ballot `last_ballot`.
+/// `roots` are the cmx roots of every election by id_ballot.
+fn expected_app_hash(
+    roots: &HashMap<String, Vec<(u32, Vec<u8>)>>,
+    elections: &[String],
+    last_ballot: u32,
+) -> Result<String> {
+    let mut hashed = vec![];
+    for id in elections.iter() {
+        let root = roots
+            .get(id)
+            .and_then(|roots| roots.iter().take_while(|(id_ballot, _)| *id_ballot <= last_ballot).last())
+            .ok_or(anyhow::anyhow!("election {id} has no cmx root"))?;
+        hashed.push(root.1.clone());
+    }
+    Ok(hex::encode(app_hash(&hashed)))
+}
+
+pub struct ElectionCheck {
+    pub errors: Vec<String>,
+    pub num_ballots: u32,
+    /// cmx root after the last ballot
+    pub latest_root: Option<Vec<u8>>,
+    /// cmx root after every ballot by id_ballot, from the initial root at 0
+    pub roots: Vec<(u32, Vec<u8>)>,
+}
+
+/// Recompute the frontiers, cmx roots, leaves and nodes of an election from its
+/// initial frontier and ballots, and check its nullifiers and ballot heights
+pub async fn check_election(connection: &mut SqliteConnection, id_election: u32) -> Result<ElectionCheck> {
+    let mut errors = vec![];
+
+    let frontiers = sqlx::query(
+        "SELECT height, frontier FROM cmx_frontiers WHERE election = ?1 ORDER BY height")
+        .bind(id_election)
+        .map(|r: SqliteRow| {
+            let height: u32 = r.get(0);
+            let frontier: Vec<u8> = r.get(1);
+            (height, frontier)
+        })
+        .fetch_all(&mut *connection)
+        .await?;
+    let roots = sqlx::query(
+        "SELECT height, hash FROM cmx_roots WHERE election = ?1 ORDER BY height")
+        .bind(id_election)
+        .map(|r: SqliteRow| {
+            let height: u32 = r.get(0);
+            let hash: Vec<u8> = r.get(1);
+            (height, hash)
+        })
+        .fetch_all(&mut *connection)
+        .await?;
+    let ballots = sqlx::query(
+        "SELECT id_ballot, height, hash, data FROM ballots WHERE election = ?1 ORDER BY height")
+        .bind(id_election)
+        .map(|r: SqliteRow| {
+            let id_ballot: u32 = r.get(0);
+            let height: u32 = r.get(1);
+            let hash: Vec<u8> = r.get(2);
+            let data: Vec<u8> = r.get(3);
+            (id_ballot, height, hash, data)
+        })
+        .fetch_all(&mut *connection)
+        .await?;
+    let leaves = sqlx::query("SELECT height, cmx FROM cmx_leaves WHERE election = ?1 ORDER BY idx")
+        .bind(id_election)
+        .map(|r: SqliteRow| {
+            let height: u32 = r.get(0);
+            let cmx: Vec<u8> = r.get(1);
+            (height, cmx)
+        })
+        .fetch_all(&mut *connection)
+        .await?;
+    let nodes = sqlx::query(
+        "SELECT level, idx, height, hash FROM cmx_nodes WHERE election = ?1 ORDER BY level, idx")
+        .bind(id_election)
+        .map(|r: SqliteRow| {
+            let level: u8 = r.get(0);
+            let idx: i64 = r.get(1);
+            let height: u32 = r.get(2);
+            let hash: Vec<u8> = r.get(3);
+            (level, idx as u64, height, hash)
+        })
+        .fetch_all(&mut *connection)
+        .await?;
+    let dnfs = sqlx::query("SELECT hash FROM dnfs WHERE election = ?1")
+        .bind(id_election)
+        .map(|r: SqliteRow| {
+            let hash: Vec<u8> = r.get(0);
+            hash
+        })
+        .fetch_all(&mut *connection)
+        .await?
+        .into_iter()
+        .collect::<HashSet<_>>();
+
+    let Some((0, initial_frontier)) = frontiers.first() else {
+        errors.push("missing initial frontier".to_string());
+        return Ok(ElectionCheck {
+            errors,
+            num_ballots: ballots.len() as u32,
+            latest_root: None,
+            roots: vec![],
+        });
+    };
+    let mut cmx_frontier = decode_frontier(initial_frontier)?;
+    let mut expected_frontiers = vec![initial_frontier.clone()];
+    // cmx roots are indexed by id_ballot
+    let mut expected_roots = vec![(0, cmx_frontier.root().to_vec())];
+    let mut nullifiers = HashSet::new();
+    let mut expected_leaves = vec![];
+    let mut expected_nodes = vec![];
+    if !ballots.is_empty() {
+        for (level, idx, hash) in completed_nodes(&cmx_frontier)? {
+            expected_nodes.push((level, idx, 0, hash.to_vec()));
+        }
+    }
+
+    for (i, (id_ballot, height, hash, data)) in ballots.iter().enumerate() {
+        if *height != i as u32 + 1 {
+            errors.push(format!("ballot height {height} should be {}", i + 1));
+        }
+        let ballot = match decode_ballot(data) {
+            Ok(ballot) => ballot,
+            Err(e) => {
+                errors.push(format!("ballot {height} cannot be decoded: {e}"));
+                continue;
+            }
+        };
+        match ballot.data.sighash() {
+            Ok(sighash) if sighash == *hash => {}
+            Ok(_) => errors.push(format!("ballot {height} sighash mismatch")),
+            Err(e) => errors.push(format!("ballot {height} has no sighash: {e}")),
+        }
+        for action in ballot.data.actions.iter() {
+            if !nullifiers.insert(action.nf.clone()) {
+                errors.push(format!("ballot {height} reuses nullifier {}", hex::encode(&action.nf)));
+            }
+            cmx_frontier.append(OrchardHash(as_byte256(&action.cmx)));
+            expected_leaves.push((*height, action.cmx.clone()));
+            for (level, idx, hash) in completed_nodes(&cmx_frontier)? {
+                expected_nodes.push((level, idx, *height, hash.to_vec()));
+            }
+        }
+        expected_frontiers.push(encode_frontier(&cmx_frontier)?);
+        expected_roots.push((*id_ballot, cmx_frontier.root().to_vec()));
+    }
+
+    if frontiers.len() != expected_frontiers.len() {
+        errors.push(format!(
+            "{} frontiers for {} ballots",
+            frontiers.len(),
+            ballots.len()
+        ));
+    }
+    for ((height, frontier), expected) in frontiers.iter().zip(expected_frontiers.iter()) {
+        if frontier != expected {
+            errors.push(format!("frontier at height {height} does not match the ballots"));
+        }
+    }
+
+    if roots.len() != expected_roots.len() {
+        errors.push(format!("{} cmx roots for {} ballots", roots.len(), ballots.len()));
+    }
+    for ((height, root), (expected_height, expected)) in roots.iter().zip(expected_roots.iter()) {
+        if height != expected_height || root != expected {
+            errors.push(format!(
+                "cmx root {} at height {height} should be {} at height {expected_height}",
+                hex::encode(root),
+                hex::encode(expected)
+            ));
+        }
+    }
+
+    if leaves != expected_leaves {
+        errors.push(format!(
+            "{} cmx leaves do not match the {} ballot outputs",
+            leaves.len(),
+            expected_leaves.len()
+        ));
+    }
+
+    expected_nodes.sort();
+    if nodes != expected_nodes {
+        errors.push(format!(
+            "{} cmx nodes do not match the {} nodes of the ballot outputs",
+            nodes.len(),
+            expected_nodes.len()
+        ));
+    }
+
+    for nf in dnfs.difference(&nullifiers) {
+        errors.push(format!("nullifier {} is not in any ballot", hex::encode(nf)));
+    }
+    for nf in nullifiers.difference(&dnfs) {
+        errors.push(format!("nullifier {} is missing", hex::encode(nf)));
+    }
+
+    let latest_root = expected_roots.last().map(|(_, root)| root.clone());
+    Ok(ElectionCheck {
+        errors,
+        num_ballots: ballots.len() as u32,
+        latest_root,
+        roots: expected_roots,
+    })
+}
+
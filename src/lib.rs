@@ -1,5 +1,17 @@
+pub mod archive;
+pub mod audit;
+pub mod backup;
 pub mod context;
 pub mod db;
+pub mod decryption;
+pub mod dump;
 pub mod election;
+pub mod fsck;
+pub mod outbox;
+pub mod rollback;
 pub mod routes;
+pub mod rpc;
+pub mod store;
 pub mod chain;
+pub mod tally;
+pub mod witness;
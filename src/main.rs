@@ -1,14 +1,33 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter},
+    sync::Arc,
+};
+
 use anyhow::{Error, Result};
 use getopt::Opt;
-use rocket::{figment::Figment, routes, tokio::runtime::Builder, Build, Config, Rocket, State};
+use rocket::{figment::Figment, routes, Build, Config, Rocket, State};
 use rocket_cors::CorsOptions;
-use tendermint_abci::ServerBuilder;
+use sqlx::SqliteConnection;
+use tendermint_abci::{Application, ServerBuilder};
 use zcash_vote_server::{
+    archive::{export_election, import_election, Archive},
+    backup::{backup, backup_path},
     chain::VoteChain,
     context::Context,
-    db::{create_schema, store_election},
-    election::scan_data_dir,
-    routes::{get_ballot_height, get_election_by_id, get_num_ballots, post_ballot},
+    db::{check_schema, create_schema},
+    dump::{diff, dump_state},
+    election::load_elections,
+    fsck::fsck,
+    outbox,
+    rollback::rollback,
+    routes::{
+        get_ballot_by_sighash, get_ballot_height, get_ballots, get_ballots_ndjson, get_cmx_roots,
+        get_compact_ballots, get_election_by_id, get_elections, get_nullifier, get_num_ballots,
+        get_queue_status, get_tx_status, get_witness, post_backup, post_ballot,
+    },
+    rpc::{CometRpc, RpcEndpoint, RpcStrategy},
+    tally::{parse_spending_key, tally, to_csv, verify_tally, Tally},
 };
 
 #[rocket::get("/")]
@@ -20,56 +39,176 @@ pub async fn init_context(config: &Figment) -> Result<Context> {
     let data_path: String = config.extract_inner("custom.data_path")?;
     let db_path: String = config.extract_inner("custom.db_path")?;
     let cometbft_port: u16 = config.extract_inner("custom.cometbft_port")?;
-    let context = Context::new(data_path, db_path, cometbft_port).await;
+    let mut context = Context::new(data_path, db_path, cometbft_port).await?;
+    context.backup_dir = config.extract_inner("custom.backup_dir").ok();
+    if let Ok(commit_timeout) = config.extract_inner("custom.commit_timeout") {
+        context.commit_timeout = commit_timeout;
+    }
+    context.outbox = config.extract_inner("custom.outbox").unwrap_or(false);
+    context.admin_token = config.extract_inner("custom.admin_token").ok();
+    if let Ok(endpoints) = config.extract_inner::<Vec<RpcEndpoint>>("custom.rpc") {
+        let strategy: RpcStrategy = config.extract_inner("custom.rpc_strategy").unwrap_or_default();
+        context.rpc = Arc::new(CometRpc::with_endpoints(endpoints, strategy));
+    }
     Ok(context)
 }
 
 pub async fn init(context: &mut Context) -> Result<()> {
-    let elections = scan_data_dir(&context.data_path)?;
-    tracing::info!("# elections = {}", elections.len());
     let mut connection = context.pool.acquire().await?;
-    sqlx::query("UPDATE elections SET closed = TRUE")
-        .execute(&mut *connection)
-        .await?;
-    for e in elections.iter() {
-        let id_election = store_election(&mut connection, e, false).await?;
-        let cmx_root = e.cmx_frontier.as_ref().unwrap().root();
-        let frontier = serde_json::to_string(&e.cmx_frontier)?;
-        sqlx::query(
-            "INSERT INTO cmx_frontiers(election, height, frontier)
-            VALUES (?1, 0, ?2) ON CONFLICT DO NOTHING",
-        )
-        .bind(id_election)
-        .bind(&frontier)
-        .execute(&mut *connection)
-        .await?;
-        sqlx::query(
-            "INSERT INTO cmx_roots(election, height, hash)
-            VALUES (?1, 0, ?2) ON CONFLICT DO NOTHING",
-        )
-        .bind(id_election)
-        .bind(&cmx_root[..])
-        .execute(&mut *connection)
-        .await?;
-    }
+    load_elections(&mut connection, &context.data_path).await?;
     Ok::<_, Error>(())
 }
 
-async fn rocket_build(config: Figment, context: Context) -> Rocket<Build> {
+async fn rocket_build(config: Figment, context: Context, app: VoteChain) -> Rocket<Build> {
     let cors = CorsOptions::default().to_cors().unwrap();
 
-    rocket::custom(config).attach(cors).manage(context).mount(
+    rocket::custom(config).attach(cors).manage(context).manage(app).mount(
         "/",
         routes![
             index,
             get_election_by_id,
+            get_elections,
             post_ballot,
             get_num_ballots,
-            get_ballot_height
+            get_ballot_height,
+            get_ballots,
+            get_ballots_ndjson,
+            get_compact_ballots,
+            get_witness,
+            get_nullifier,
+            get_ballot_by_sighash,
+            get_tx_status,
+            get_queue_status,
+            get_cmx_roots,
+            post_backup
         ],
     )
 }
 
+/// The election key is read from `ELECTION_KEY` or else from the first
+/// line of stdin, so that it does not show in the process list
+fn read_election_key() -> Result<String> {
+    if let Ok(key) = std::env::var("ELECTION_KEY") {
+        return Ok(key.trim().to_string());
+    }
+    let mut key = String::new();
+    std::io::stdin().read_line(&mut key)?;
+    Ok(key.trim().to_string())
+}
+
+async fn run_command(connection: &mut SqliteConnection, commands: &[String]) -> Result<()> {
+    match commands[0].as_str() {
+        "tally" => {
+            // tally <election id> [json|csv]
+            let Some(id) = commands.get(1) else {
+                anyhow::bail!("Usage: tally <election id> [json|csv]");
+            };
+            let sk = parse_spending_key(&read_election_key()?)?;
+            let tally = tally(connection, id, &sk).await?;
+            match commands.get(2).map(String::as_str) {
+                Some("csv") => print!("{}", to_csv(&tally)),
+                _ => println!("{}", serde_json::to_string_pretty(&tally)?),
+            }
+        }
+        "verify-tally" => {
+            // verify-tally <tally json file>
+            let Some(path) = commands.get(1) else {
+                anyhow::bail!("Usage: verify-tally <tally json file>");
+            };
+            let tally: Tally = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+            let errors = verify_tally(connection, &tally).await?;
+            for e in errors.iter() {
+                println!("{e}");
+            }
+            if !errors.is_empty() {
+                eprintln!("{} errors found", errors.len());
+                std::process::exit(1);
+            }
+            println!("Tally verified: {} outputs", tally.outputs.len());
+        }
+        "fsck" => {
+            let errors = fsck(connection).await?;
+            for e in errors.iter() {
+                println!("{e}");
+            }
+            if !errors.is_empty() {
+                eprintln!("{} errors found", errors.len());
+                std::process::exit(1);
+            }
+            println!("Database is consistent");
+        }
+        "export" => {
+            // export <election id> <archive file>
+            let (Some(id), Some(path)) = (commands.get(1), commands.get(2)) else {
+                anyhow::bail!("Usage: export <election id> <archive file>");
+            };
+            let archive = export_election(connection, id).await?;
+            serde_json::to_writer(BufWriter::new(File::create(path)?), &archive)?;
+            println!(
+                "Exported {} ballots, hash {}",
+                archive.manifest.ballots, archive.manifest.hash
+            );
+        }
+        "import" => {
+            // import <archive file>
+            let Some(path) = commands.get(1) else {
+                anyhow::bail!("Usage: import <archive file>");
+            };
+            let archive: Archive = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+            import_election(connection, &archive).await?;
+            println!(
+                "Imported election {} with {} ballots",
+                archive.manifest.election, archive.manifest.ballots
+            );
+        }
+        "backup" => {
+            // backup <file>
+            let Some(path) = commands.get(1) else {
+                anyhow::bail!("Usage: backup <file>");
+            };
+            let info = backup(connection, std::path::Path::new(path)).await?;
+            println!("Backup at height {} app hash {}", info.height, info.app_hash);
+        }
+        "rollback" => {
+            // rollback --to-height <height>
+            let height = match (commands.get(1).map(String::as_str), commands.get(2)) {
+                (Some("--to-height"), Some(height)) => height.parse::<u32>()?,
+                _ => anyhow::bail!("Usage: rollback --to-height <height>"),
+            };
+            let app_state = rollback(connection, height).await?;
+            println!("Rolled back to height {} app hash {}", app_state.height, app_state.hash);
+        }
+        "dump-state" => {
+            // dump-state [--height <height>]
+            let height = match (commands.get(1).map(String::as_str), commands.get(2)) {
+                (None, _) => None,
+                (Some("--height"), Some(height)) => Some(height.parse::<u32>()?),
+                _ => anyhow::bail!("Usage: dump-state [--height <height>]"),
+            };
+            let dump = dump_state(connection, height).await?;
+            // through Value to sort the keys
+            println!("{}", serde_json::to_string_pretty(&serde_json::to_value(&dump)?)?);
+        }
+        "diff" => {
+            // diff <dump file> <dump file>
+            let (Some(a), Some(b)) = (commands.get(1), commands.get(2)) else {
+                anyhow::bail!("Usage: diff <dump file> <dump file>");
+            };
+            let a: serde_json::Value = serde_json::from_reader(BufReader::new(File::open(a)?))?;
+            let b: serde_json::Value = serde_json::from_reader(BufReader::new(File::open(b)?))?;
+            match diff(&a, &b) {
+                Some(d) => {
+                    println!("First difference at {d}");
+                    std::process::exit(1);
+                }
+                None => println!("Dumps are identical"),
+            }
+        }
+        command => anyhow::bail!("Unknown command {command}"),
+    }
+    Ok(())
+}
+
 #[rocket::main]
 pub async fn main() {
     let subscriber = tracing_subscriber::fmt()
@@ -91,32 +230,67 @@ pub async fn main() {
             },
         }
     }
+    let commands = args[opts.index()..].to_vec();
 
     let config = Config::figment();
     let mut context = init_context(&config).await.unwrap();
     let mut connection = context.pool.acquire().await.unwrap();
-    {
+    if commands.first().map(String::as_str) == Some("fsck") {
+        // fsck checks the database as it is, without upgrading it
+        if let Err(e) = check_schema(&mut connection).await {
+            eprintln!("{e}");
+            std::process::exit(2);
+        }
+    } else {
         create_schema(&mut connection).await.unwrap();
-        init(&mut context).await.unwrap();
     }
 
+    if !commands.is_empty() {
+        // exit code 1 is for discrepancies
+        if let Err(e) = run_command(&mut connection, &commands).await {
+            eprintln!("{e}");
+            std::process::exit(2);
+        }
+        return;
+    }
+
+    init(&mut context).await.unwrap();
+
     if q_flag {
         return;
     }
 
     let pool = context.pool.clone();
     let (app, runner) = VoteChain::new(pool).await;
+    let block_tx_height: u32 = config.extract_inner("custom.block_tx_height").unwrap_or(0);
+    let runner = runner.with_block_tx_height(block_tx_height);
     let server = ServerBuilder::new(1_000_000)
-        .bind(format!("{}:{}", "127.0.0.1", context.comet_bft), app)
+        .bind(format!("{}:{}", "127.0.0.1", context.comet_bft), app.clone())
         .unwrap();
-    std::thread::spawn(move || {
-        let r = Builder::new_current_thread().enable_all().build().unwrap();
-        r.block_on(async move {
-            let res = runner.run().await;
-            println!("{:?}", res);
-        })
-    });
+    runner.spawn();
     std::thread::spawn(move || server.listen().unwrap());
 
-    rocket_build(config, context).await.launch().await.unwrap();
+    let health_interval: u64 = config.extract_inner("custom.rpc_health_interval").unwrap_or(10);
+    context.rpc.clone().spawn_health_checks(health_interval);
+    if context.outbox {
+        outbox::spawn(context.pool.clone(), context.rpc.clone());
+    }
+
+    let backup_interval: Option<u64> = config.extract_inner("custom.backup_interval").ok();
+    if let (Some(dir), Some(interval)) = (context.backup_dir.clone(), backup_interval) {
+        let app = app.clone();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(std::time::Duration::from_secs(interval));
+            // no new block since the last backup
+            let height = app.info(Default::default()).last_block_height;
+            if backup_path(&dir, height as u32).exists() {
+                continue;
+            }
+            if let Err(e) = app.backup(&dir) {
+                tracing::error!("Scheduled backup failed: {e}");
+            }
+        });
+    }
+
+    rocket_build(config, context, app).await.launch().await.unwrap();
 }
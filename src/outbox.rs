@@ -0,0 +1,141 @@
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sqlx::{SqliteConnection, SqlitePool};
+
+use crate::{
+    chain::tx_hash,
+    rpc::{CheckTxError, CometRpc},
+};
+
+/// seconds between broadcasts of a tx that is in the mempool,
+/// in case the node drops it
+const RECHECK_INTERVAL: i64 = 60;
+const MAX_BACKOFF: i64 = 60;
+
+/// A tx of the outbox. `status` is queued, pending, committed or rejected.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct OutboxEntry {
+    pub id: u32,
+    pub election: String,
+    pub tx_hash: String,
+    pub status: String,
+    pub attempts: u32,
+    /// last broadcast error
+    pub error: Option<String>,
+}
+
+pub fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64)
+}
+
+/// Queue a tx for broadcast. A tx that is already queued keeps its id.
+pub async fn enqueue(connection: &mut SqliteConnection, election: &str, tx: &[u8]) -> Result<u32> {
+    let hash = tx_hash(tx);
+    sqlx::query(
+        "INSERT INTO outbox(election, tx_hash, tx, status, attempts, next_attempt)
+        VALUES (?1, ?2, ?3, 'queued', 0, ?4) ON CONFLICT DO NOTHING")
+        .bind(election)
+        .bind(&hash)
+        .bind(tx)
+        .bind(now())
+        .execute(&mut *connection)
+        .await?;
+    let (id,): (u32,) = sqlx::query_as("SELECT id_outbox FROM outbox WHERE tx_hash = ?1")
+        .bind(&hash)
+        .fetch_one(&mut *connection)
+        .await?;
+    Ok(id)
+}
+
+pub async fn get_entry(connection: &mut SqliteConnection, id: u32) -> Result<Option<OutboxEntry>> {
+    let r: Option<(String, String, String, u32, Option<String>)> = sqlx::query_as(
+        "SELECT election, tx_hash, status, attempts, error FROM outbox WHERE id_outbox = ?1")
+        .bind(id)
+        .fetch_optional(&mut *connection)
+        .await?;
+    Ok(r.map(|(election, tx_hash, status, attempts, error)| OutboxEntry {
+        id,
+        election,
+        tx_hash,
+        status,
+        attempts,
+        error,
+    }))
+}
+
+/// Seconds before the next attempt after `attempts` failures
+fn backoff(attempts: u32) -> i64 {
+    (1i64 << attempts.min(6)).min(MAX_BACKOFF)
+}
+
+/// Mark the committed txs, then broadcast the txs that are due at `now`
+pub async fn process(connection: &mut SqliteConnection, rpc: &CometRpc, now: i64) -> Result<()> {
+    sqlx::query(
+        "UPDATE outbox SET status = 'committed', error = NULL
+        WHERE status IN ('queued', 'pending')
+        AND EXISTS (SELECT 1 FROM ballots b WHERE b.tx_hash = outbox.tx_hash)")
+        .execute(&mut *connection)
+        .await?;
+
+    let due: Vec<(u32, Vec<u8>, u32)> = sqlx::query_as(
+        "SELECT id_outbox, tx, attempts FROM outbox
+        WHERE status IN ('queued', 'pending') AND next_attempt <= ?1
+        ORDER BY next_attempt LIMIT 100")
+        .bind(now)
+        .fetch_all(&mut *connection)
+        .await?;
+
+    for (id, tx, attempts) in due {
+        let attempts = attempts + 1;
+        let (status, error) = match rpc.broadcast_tx_sync(&tx).await {
+            Ok(_) => ("pending", None),
+            Err(e) if e.is::<CheckTxError>() => ("rejected", Some(e.to_string())),
+            // CometBFT reports a tx that is still in its mempool as an error
+            Err(e) if e.to_string().contains("already exists in cache") => ("pending", None),
+            Err(e) => ("queued", Some(e.to_string())),
+        };
+        let next_attempt = now + match status {
+            "pending" => RECHECK_INTERVAL,
+            _ => backoff(attempts),
+        };
+        if let Some(error) = &error {
+            tracing::warn!("Outbox tx {id} {status}: {error}");
+        }
+        // a tx committed in the meantime is rejected as a double spend
+        sqlx::query(
+            "UPDATE outbox SET status = ?2, attempts = ?3, next_attempt = ?4, error = ?5
+            WHERE id_outbox = ?1
+            AND NOT EXISTS (SELECT 1 FROM ballots b WHERE b.tx_hash = outbox.tx_hash)")
+            .bind(id)
+            .bind(status)
+            .bind(attempts)
+            .bind(next_attempt)
+            .bind(error)
+            .execute(&mut *connection)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Process the outbox every second
+pub fn spawn(pool: SqlitePool, rpc: Arc<CometRpc>) {
+    rocket::tokio::spawn(async move {
+        loop {
+            let res = async {
+                let mut connection = pool.acquire().await?;
+                process(&mut connection, &rpc, now()).await
+            };
+            if let Err(e) = res.await {
+                tracing::error!("Outbox failed: {e}");
+            }
+            rocket::tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+    });
+}
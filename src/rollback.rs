@@ -0,0 +1,97 @@
+use anyhow::Result;
+use sqlx::{sqlite::SqliteRow, Connection, Row, SqliteConnection};
+
+use crate::{
+    db::{decode_ballot, AppState},
+    store::VoteStore,
+};
+
+/// Remove the ballots, nullifiers, frontiers, cmx roots and leaves added after
+/// block `height` and restore the app state of that block.
+/// The node must be stopped.
+pub async fn rollback(connection: &mut SqliteConnection, height: u32) -> Result<AppState> {
+    let mut db_tx = connection.begin().await?;
+    let (app_state, last_ballot) = if height == 0 {
+        (AppState::initial(), 0)
+    } else {
+        let (hash, last_ballot, elections): (String, u32, Option<String>) = sqlx::query_as(
+            "SELECT hash, last_ballot, elections FROM app_states WHERE height = ?1")
+            .bind(height)
+            .fetch_optional(&mut *db_tx)
+            .await?
+            .ok_or(anyhow::anyhow!("No app state recorded at height {height}"))?;
+        // not recorded by older versions
+        let elections = match elections {
+            Some(elections) => serde_json::from_str(&elections)?,
+            None => vec![],
+        };
+        let app_state = AppState {
+            height,
+            hash,
+            elections,
+        };
+        (app_state, last_ballot)
+    };
+    let current = db_tx.app_state().await?;
+    if current.height < height {
+        anyhow::bail!("Current height {} is below {height}", current.height);
+    }
+
+    let ballots = sqlx::query(
+        "SELECT election, data FROM ballots WHERE id_ballot > ?1")
+        .bind(last_ballot)
+        .map(|r: SqliteRow| {
+            let id_election: u32 = r.get(0);
+            let data: Vec<u8> = r.get(1);
+            (id_election, data)
+        })
+        .fetch_all(&mut *db_tx)
+        .await?;
+    for (id_election, data) in ballots.iter() {
+        let ballot = decode_ballot(data)?;
+        for action in ballot.data.actions.iter() {
+            sqlx::query("DELETE FROM dnfs WHERE election = ?1 AND hash = ?2")
+                .bind(id_election)
+                .bind(&action.nf)
+                .execute(&mut *db_tx)
+                .await?;
+        }
+    }
+    sqlx::query("DELETE FROM ballots WHERE id_ballot > ?1")
+        .bind(last_ballot)
+        .execute(&mut *db_tx)
+        .await?;
+    // cmx roots are indexed by id_ballot
+    sqlx::query("DELETE FROM cmx_roots WHERE height > ?1")
+        .bind(last_ballot)
+        .execute(&mut *db_tx)
+        .await?;
+    // cmx frontiers are indexed by the ballot height in the election
+    sqlx::query(
+        "DELETE FROM cmx_frontiers WHERE height >
+        (SELECT COUNT(*) FROM ballots b WHERE b.election = cmx_frontiers.election)")
+        .execute(&mut *db_tx)
+        .await?;
+    sqlx::query(
+        "DELETE FROM cmx_leaves WHERE height >
+        (SELECT COUNT(*) FROM ballots b WHERE b.election = cmx_leaves.election)")
+        .execute(&mut *db_tx)
+        .await?;
+    sqlx::query(
+        "DELETE FROM cmx_nodes WHERE height >
+        (SELECT COUNT(*) FROM ballots b WHERE b.election = cmx_nodes.election)")
+        .execute(&mut *db_tx)
+        .await?;
+    sqlx::query("DELETE FROM app_states WHERE height > ?1")
+        .bind(height)
+        .execute(&mut *db_tx)
+        .await?;
+    db_tx.store_app_state(&app_state).await?;
+    db_tx.commit().await?;
+
+    tracing::info!(
+        "Rolled back {} ballots to height {height}",
+        ballots.len()
+    );
+    Ok(app_state)
+}
@@ -1,13 +1,30 @@
-use std::time::Duration;
+use std::{convert::Infallible, net::SocketAddr, time::Duration};
 
 use anyhow::Error;
-use base64::{prelude::BASE64_STANDARD, Engine as _};
 use orchard::vote::Ballot;
-use rocket::{http::Status, response::status::Custom, serde::json::Json, State};
+use rocket::{
+    futures::StreamExt,
+    http::{ContentType, Status},
+    request::{FromRequest, Outcome},
+    response::{status::Custom, stream::TextStream},
+    serde::json::Json,
+    tokio::sync::broadcast::error::RecvError,
+    Request, State,
+};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use sqlx::SqliteConnection;
+use zcash_note_encryption::COMPACT_NOTE_SIZE;
+use zcash_vote::election::{CandidateChoice, Election};
 
-use crate::{context::Context, db::get_election};
+use crate::{
+    backup::BackupInfo,
+    chain::{check_ballot_state, BallotError, VoteChain},
+    context::Context,
+    db::{get_election, BlockRef},
+    outbox::{enqueue, get_entry},
+    witness::{witness, Witness},
+};
 
 #[derive(Serialize, Deserialize)]
 pub struct Tx {
@@ -15,6 +32,81 @@ pub struct Tx {
     pub ballot: Ballot,
 }
 
+/// The definition of an election without its cmx frontier, with its state
+#[derive(Serialize, Deserialize)]
+pub struct ElectionSummary {
+    pub id: String,
+    pub name: String,
+    pub closed: bool,
+    pub num_ballots: u32,
+    /// latest cmx root
+    pub cmx_root: String,
+    pub start_height: u32,
+    pub end_height: u32,
+    pub question: String,
+    pub candidates: Vec<CandidateChoice>,
+    pub signature_required: bool,
+    /// nullifier root of the snapshot
+    pub nf: String,
+    /// cmx root of the snapshot
+    pub cmx: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ElectionList {
+    pub total: u32,
+    pub offset: u32,
+    pub limit: u32,
+    pub elections: Vec<ElectionSummary>,
+}
+
+const MAX_LIMIT: u32 = 1000;
+const MAX_COMPACT_LIMIT: u32 = 10000;
+
+/// Elections ordered by id, optionally only the open or closed ones
+#[rocket::get("/elections?<closed>&<offset>&<limit>")]
+pub async fn get_elections(
+    closed: Option<bool>,
+    offset: Option<u32>,
+    limit: Option<u32>,
+    state: &State<Context>,
+) -> Result<Json<ElectionList>, Custom<String>> {
+    let res = async {
+        let offset = offset.unwrap_or(0);
+        let limit = limit.unwrap_or(100).min(MAX_LIMIT);
+        let mut connection = state.pool.acquire().await?;
+        let total = crate::db::count_elections(&mut connection, closed).await?;
+        let rows = crate::db::list_elections(&mut connection, closed, offset, limit).await?;
+        let mut elections = vec![];
+        for (id_election, id, definition, closed) in rows {
+            let election = serde_json::from_str::<Election>(&definition)?;
+            let num_ballots = crate::db::get_num_ballots(&mut connection, id_election).await?;
+            let cmx_root = crate::db::get_latest_cmx_root(&mut connection, id_election).await?;
+            elections.push(ElectionSummary {
+                id,
+                name: election.name,
+                closed,
+                num_ballots,
+                cmx_root: hex::encode(cmx_root),
+                start_height: election.start_height,
+                end_height: election.end_height,
+                question: election.question,
+                candidates: election.candidates,
+                signature_required: election.signature_required,
+                nf: hex::encode(election.nf.0),
+                cmx: hex::encode(election.cmx.0),
+            });
+        }
+        Ok::<_, Error>(Json(ElectionList {
+            total,
+            offset,
+            limit,
+            elections,
+        }))
+    };
+    res.await.map_err(|e| Custom(Status::InternalServerError, e.to_string()))
+}
+
 #[rocket::get("/election/<id>")]
 pub async fn get_election_by_id(id: &str, state: &State<Context>) -> Result<Json<Value>, String> {
     let res = async {
@@ -36,12 +128,194 @@ pub async fn get_ballot_height(
         let mut connection = state.pool.acquire().await?;
         let (id_election, _, _) = get_election(&mut connection, &id).await?;
         let ballot = crate::db::get_ballot_height(&mut connection, id_election, height).await?;
-        let ballot = serde_json::from_str::<Value>(&ballot)?;
-        Ok::<_, Error>(Json(ballot))
+        let block = crate::db::get_ballot_block(&mut connection, id_election, height).await?;
+        Ok::<_, Error>(Json(ballot_json(height, &ballot, block)?))
     };
     res.await.map_err(|e| Custom(Status::InternalServerError, e.to_string()))
 }
 
+#[rocket::get("/election/<id>/ballot/<sighash>")]
+pub async fn get_ballot_by_sighash(
+    id: &str,
+    sighash: &str,
+    state: &State<Context>,
+) -> Result<Json<Value>, Custom<String>> {
+    let res = async {
+        let sighash = hex::decode(sighash)?;
+        let mut connection = state.pool.acquire().await?;
+        let (id_election, _, _) = get_election(&mut connection, id).await?;
+        let ballot = crate::db::get_ballot_by_sighash(&mut connection, id_election, &sighash).await?;
+        let ballot = match ballot {
+            Some((height, ballot, block)) => Some(Json(ballot_json(height, &ballot, block)?)),
+            None => None,
+        };
+        Ok::<_, Error>(ballot)
+    };
+    match res.await {
+        Ok(Some(ballot)) => Ok(ballot),
+        Ok(None) => Err(Custom(Status::NotFound, "Unknown ballot".to_string())),
+        Err(e) => Err(Custom(Status::InternalServerError, e.to_string())),
+    }
+}
+
+/// Ballot JSON with its height and block
+fn ballot_json(height: u32, ballot: &Ballot, block: Option<BlockRef>) -> Result<Value, Error> {
+    let mut ballot = serde_json::to_value(ballot)?;
+    ballot["height"] = height.into();
+    ballot["block"] = serde_json::to_value(&block)?;
+    Ok(ballot)
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BallotPage {
+    pub ballots: Vec<Value>,
+    /// height to start the next page from, if there are more ballots
+    pub next: Option<u32>,
+}
+
+/// Ballots with `from <= height <= to`, at most `limit`
+#[rocket::get("/election/<id>/ballots?<from>&<to>&<limit>")]
+pub async fn get_ballots(
+    id: &str,
+    from: Option<u32>,
+    to: Option<u32>,
+    limit: Option<u32>,
+    state: &State<Context>,
+) -> Result<Json<BallotPage>, Custom<String>> {
+    let res = async {
+        let from = from.unwrap_or(1);
+        let to = to.unwrap_or(u32::MAX);
+        let limit = limit.unwrap_or(100).min(MAX_LIMIT);
+        let mut connection = state.pool.acquire().await?;
+        let (id_election, _, _) = get_election(&mut connection, id).await?;
+        let rows = crate::db::get_ballot_range(&mut connection, id_election, from, to, limit).await?;
+        let next = match rows.last() {
+            Some((height, _, _)) if rows.len() as u32 == limit && *height < to => Some(height + 1),
+            _ => None,
+        };
+        let ballots = rows
+            .iter()
+            .map(|(height, ballot, block)| ballot_json(*height, ballot, block.clone()))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok::<_, Error>(Json(BallotPage { ballots, next }))
+    };
+    res.await.map_err(|e| Custom(Status::InternalServerError, e.to_string()))
+}
+
+/// Fields of a ballot action needed to trial decrypt its output
+#[derive(Serialize, Deserialize)]
+pub struct CompactAction {
+    pub nf: String,
+    pub cmx: String,
+    pub epk: String,
+    /// first `COMPACT_NOTE_SIZE` bytes of the note ciphertext
+    pub enc: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CompactBallot {
+    pub height: u32,
+    pub sighash: String,
+    pub actions: Vec<CompactAction>,
+}
+
+impl CompactBallot {
+    pub fn new(height: u32, ballot: &Ballot) -> Result<Self, Error> {
+        let actions = ballot
+            .data
+            .actions
+            .iter()
+            .map(|a| CompactAction {
+                nf: hex::encode(&a.nf),
+                cmx: hex::encode(&a.cmx),
+                epk: hex::encode(&a.epk),
+                enc: hex::encode(&a.enc[..COMPACT_NOTE_SIZE.min(a.enc.len())]),
+            })
+            .collect();
+        Ok(CompactBallot {
+            height,
+            sighash: hex::encode(ballot.data.sighash()?),
+            actions,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CompactBallotPage {
+    pub ballots: Vec<CompactBallot>,
+    pub next: Option<u32>,
+}
+
+/// Like `/ballots` but without proofs and signatures, for wallets
+/// that scan the ballot outputs
+#[rocket::get("/election/<id>/compact_ballots?<from>&<to>&<limit>")]
+pub async fn get_compact_ballots(
+    id: &str,
+    from: Option<u32>,
+    to: Option<u32>,
+    limit: Option<u32>,
+    state: &State<Context>,
+) -> Result<Json<CompactBallotPage>, Custom<String>> {
+    let res = async {
+        let from = from.unwrap_or(1);
+        let to = to.unwrap_or(u32::MAX);
+        let limit = limit.unwrap_or(1000).min(MAX_COMPACT_LIMIT);
+        let mut connection = state.pool.acquire().await?;
+        let (id_election, _, _) = get_election(&mut connection, id).await?;
+        let rows = crate::db::get_ballot_range(&mut connection, id_election, from, to, limit).await?;
+        let next = match rows.last() {
+            Some((height, _, _)) if rows.len() as u32 == limit && *height < to => Some(height + 1),
+            _ => None,
+        };
+        let ballots = rows
+            .iter()
+            .map(|(height, ballot, _)| CompactBallot::new(*height, ballot))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok::<_, Error>(Json(CompactBallotPage { ballots, next }))
+    };
+    res.await.map_err(|e| Custom(Status::InternalServerError, e.to_string()))
+}
+
+/// Every ballot of the election in height order, one JSON per line
+#[rocket::get("/election/<id>/ballots.ndjson")]
+pub async fn get_ballots_ndjson(
+    id: &str,
+    state: &State<Context>,
+) -> Result<(ContentType, TextStream![String]), Custom<String>> {
+    let res = async {
+        let mut connection = state.pool.acquire().await?;
+        let (id_election, _, _) = get_election(&mut connection, id).await?;
+        Ok::<_, Error>(id_election)
+    };
+    let id_election = res.await.map_err(|e| Custom(Status::InternalServerError, e.to_string()))?;
+    let pool = state.pool.clone();
+    let stream = TextStream! {
+        let Ok(mut connection) = pool.acquire().await else {
+            return;
+        };
+        let mut rows = sqlx::query(
+            "SELECT height, data, block_height, block_time, tx_hash FROM ballots
+            WHERE election = ?1 ORDER BY height")
+            .bind(id_election)
+            .fetch(&mut *connection);
+        while let Some(row) = rows.next().await {
+            let line = row
+                .map_err(Error::from)
+                .and_then(crate::db::ballot_row)
+                .and_then(|(height, ballot, block)| ballot_json(height, &ballot, block))
+                .map(|v| v.to_string());
+            match line {
+                Ok(line) => yield line + "\n",
+                Err(e) => {
+                    tracing::error!("ballot stream: {e}");
+                    return;
+                }
+            }
+        }
+    };
+    Ok((ContentType::new("application", "x-ndjson"), stream))
+}
+
 #[rocket::get("/election/<id>/num_ballots")]
 pub async fn get_num_ballots(id: &str, state: &State<Context>) -> Result<String, Custom<String>> {
     let res = async {
@@ -53,43 +327,359 @@ pub async fn get_num_ballots(id: &str, state: &State<Context>) -> Result<String,
     res.await.map_err(|e| Custom(Status::InternalServerError, e.to_string()))
 }
 
-#[rocket::post("/election/<id>/ballot", format = "json", data = "<ballot>")]
+#[rocket::get("/election/<id>/cmx_roots")]
+pub async fn get_cmx_roots(id: &str, state: &State<Context>) -> Result<Json<Vec<String>>, Custom<String>> {
+    let res = async {
+        let mut connection = state.pool.acquire().await?;
+        let (id_election, _, _) = get_election(&mut connection, &id).await?;
+        let roots = crate::db::get_cmx_roots(&mut connection, id_election).await?;
+        Ok::<_, Error>(Json(roots.iter().map(hex::encode).collect()))
+    };
+    res.await.map_err(|e| Custom(Status::InternalServerError, e.to_string()))
+}
+
+/// Merkle path of a ballot output, against the cmx root after
+/// `anchor_height` ballots or the latest one
+#[rocket::get("/election/<id>/witness/<cmx>?<anchor_height>")]
+pub async fn get_witness(
+    id: &str,
+    cmx: &str,
+    anchor_height: Option<u32>,
+    state: &State<Context>,
+) -> Result<Json<Witness>, Custom<String>> {
+    let res = async {
+        let cmx = hex::decode(cmx)?;
+        let mut connection = state.pool.acquire().await?;
+        let (id_election, _, _) = get_election(&mut connection, id).await?;
+        let witness = witness(&mut connection, id_election, &cmx, anchor_height).await?;
+        Ok::<_, Error>(Json(witness))
+    };
+    res.await.map_err(|e| Custom(Status::InternalServerError, e.to_string()))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct NullifierStatus {
+    pub nullifier: String,
+    pub spent: bool,
+    /// height and sighash of the ballot that spent it
+    pub height: Option<u32>,
+    pub sighash: Option<String>,
+    /// only with `mempool=true`: a ballot waiting in the mempool spends it
+    pub pending: Option<bool>,
+}
+
+/// Whether a nullifier was used to vote, and optionally
+/// whether a ballot in the mempool uses it
+#[rocket::get("/election/<id>/nullifier/<nf>?<mempool>")]
+pub async fn get_nullifier(
+    id: &str,
+    nf: &str,
+    mempool: Option<bool>,
+    state: &State<Context>,
+    chain: &State<VoteChain>,
+) -> Result<Json<NullifierStatus>, Custom<String>> {
+    let res = async {
+        let nf_bytes = hex::decode(nf)?;
+        let mut connection = state.pool.acquire().await?;
+        let (id_election, _, _) = get_election(&mut connection, id).await?;
+        let spent = crate::db::get_nullifier(&mut connection, id_election, &nf_bytes).await?;
+        let pending = mempool
+            .unwrap_or(false)
+            .then(|| chain.mempool().spends(id, &nf_bytes));
+        Ok::<_, Error>(Json(NullifierStatus {
+            nullifier: hex::encode(&nf_bytes),
+            spent: spent.is_some(),
+            height: spent.as_ref().map(|(height, _)| *height),
+            sighash: spent.map(|(_, hash)| hex::encode(hash)),
+            pending,
+        }))
+    };
+    res.await.map_err(|e| Custom(Status::InternalServerError, e.to_string()))
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum TxStatus {
+    /// waiting in the mempool
+    Pending,
+    Committed {
+        election: String,
+        /// ballot height in the election
+        height: u32,
+        block_height: u32,
+    },
+    /// by check_tx or finalize_block
+    Rejected { error: String },
+    /// or rejected before the node last restarted
+    Unknown,
+    /// in the outbox, not accepted by a node yet
+    Queued,
+}
+
+/// Status of a tx submitted with `post_ballot`, by its hash
+#[rocket::get("/tx/<hash>/status")]
+pub async fn get_tx_status(
+    hash: &str,
+    state: &State<Context>,
+    chain: &State<VoteChain>,
+) -> Result<Json<TxStatus>, Custom<String>> {
+    let res = async {
+        let hash = hash.to_uppercase();
+        let mut connection = state.pool.acquire().await?;
+        if let Some((election, height, block_height)) =
+            crate::db::get_ballot_by_tx_hash(&mut connection, &hash).await?
+        {
+            return Ok(TxStatus::Committed {
+                election,
+                height,
+                block_height,
+            });
+        }
+        if chain.mempool().contains(&hash) {
+            return Ok(TxStatus::Pending);
+        }
+        if let Some(error) = chain.rejection(&hash) {
+            return Ok(TxStatus::Rejected { error });
+        }
+        Ok::<_, Error>(TxStatus::Unknown)
+    };
+    res.await
+        .map(Json)
+        .map_err(|e| Custom(Status::InternalServerError, e.to_string()))
+}
+
+#[derive(rocket::Responder)]
+pub enum BallotResponse {
+    /// tx hash
+    Hash(String),
+    Committed(Json<TxStatus>),
+    Queued(Json<QueueStatus>),
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct QueueStatus {
+    pub id: u32,
+    pub tx_hash: String,
+    pub attempts: u32,
+    /// error of the last broadcast, if it is retried
+    pub last_error: Option<String>,
+    #[serde(flatten)]
+    pub status: TxStatus,
+}
+
+async fn queue_status(connection: &mut SqliteConnection, id: u32) -> Result<Option<QueueStatus>, Error> {
+    let Some(entry) = get_entry(connection, id).await? else {
+        return Ok(None);
+    };
+    let committed = crate::db::get_ballot_by_tx_hash(connection, &entry.tx_hash).await?;
+    let (status, last_error) = match (entry.status.as_str(), committed) {
+        (_, Some((election, height, block_height))) => (
+            TxStatus::Committed {
+                election,
+                height,
+                block_height,
+            },
+            None,
+        ),
+        ("queued", _) => (TxStatus::Queued, entry.error),
+        ("rejected", _) => (
+            TxStatus::Rejected {
+                error: entry.error.unwrap_or_default(),
+            },
+            None,
+        ),
+        // committed but rolled back since
+        _ => (TxStatus::Pending, None),
+    };
+    Ok(Some(QueueStatus {
+        id,
+        tx_hash: entry.tx_hash,
+        attempts: entry.attempts,
+        last_error,
+        status,
+    }))
+}
+
+/// Run the checks of check_tx before the ballot is broadcast.
+/// The proofs are checked last, on a blocking thread.
+async fn check(
+    state: &State<Context>,
+    chain: &VoteChain,
+    id: &str,
+    ballot: &Ballot,
+) -> Result<(), BallotError> {
+    let mut connection = state.pool.acquire().await.map_err(|e| BallotError::Store(e.into()))?;
+    let election = check_ballot_state(&mut *connection, id, ballot).await?;
+    let ballot = ballot.clone();
+    let proof_check = chain.proof_check();
+    rocket::tokio::task::spawn_blocking(move || proof_check(&ballot, &election))
+        .await
+        .map_err(|e| BallotError::Store(e.into()))?
+}
+
+/// Broadcast a ballot once it passes the checks of check_tx. `wait` is
+/// - `async`: returns the tx hash right away
+/// - `sync` (default): returns the tx hash once check_tx has passed
+/// - `commit`: returns the ballot height once its block is committed
+/// - `queue`: stores the ballot in the outbox and returns its queue id.
+///   It is broadcast until it is committed or rejected.
+#[rocket::post("/election/<id>/ballot?<wait>", format = "json", data = "<ballot>")]
 pub async fn post_ballot(
     id: &str,
+    wait: Option<&str>,
     ballot: Json<Ballot>,
     state: &State<Context>,
-) -> Result<String, Custom<String>> {
+    chain: &State<VoteChain>,
+) -> Result<BallotResponse, Custom<String>> {
+    let wait = wait.unwrap_or("sync");
+    if !["async", "sync", "commit", "queue"].contains(&wait) {
+        return Err(Custom(Status::BadRequest, format!("Unknown wait mode {wait}")));
+    }
+    if wait == "queue" && !state.outbox {
+        return Err(Custom(Status::NotFound, "outbox is not enabled".to_string()));
+    }
+    let ballot = ballot.into_inner();
+    check(state, chain, id, &ballot).await.map_err(|e| {
+        let status = match &e {
+            BallotError::UnknownElection => Status::NotFound,
+            BallotError::ElectionClosed | BallotError::DoubleSpend => Status::Conflict,
+            BallotError::Store(_) => Status::InternalServerError,
+            _ => Status::BadRequest,
+        };
+        Custom(status, e.to_string())
+    })?;
     let res = async {
-        let comet_bft = state.comet_bft;
         tracing::info!("Ballot received");
         let tx = Tx {
             id: id.to_string(),
-            ballot: ballot.into_inner(),
+            ballot,
         };
         let tx_bytes = bincode::serialize(&tx).unwrap();
-
-        let rpc_port = comet_bft - 1;
-        let tx_data = BASE64_STANDARD.encode(&tx_bytes);
-        let req_body = serde_json::json!({
-            "id": "",
-            "method": "broadcast_tx_sync",
-            "params": [tx_data]
-        });
-        let url = format!("http://127.0.0.1:{rpc_port}/v1");
-        tracing::info!("Post to {}", url);
-        let client = reqwest::Client::new();
-        let rep = client.post(&url)
-            .timeout(Duration::from_secs(300))
-            .json(&req_body).send().await?.error_for_status()?;
-        let json_rep: Value = rep.json().await?;
-        tracing::info!("post ballot rep: {:?}", json_rep);
-        if let Some(error_msg) = json_rep.pointer("/error/data") {
-            anyhow::bail!(error_msg.as_str().unwrap().to_string());
+        let rpc = &state.rpc;
+        match wait {
+            "async" => Ok(BallotResponse::Hash(rpc.broadcast_tx_async(&tx_bytes).await?)),
+            "sync" => Ok(BallotResponse::Hash(rpc.broadcast_tx_sync(&tx_bytes).await?)),
+            "commit" => {
+                // subscribe first to not miss the event
+                let mut events = chain.subscribe();
+                let hash = rpc.broadcast_tx_sync(&tx_bytes).await?;
+                let timeout = Duration::from_secs(state.commit_timeout);
+                let event = rocket::tokio::time::timeout(timeout, async {
+                    loop {
+                        match events.recv().await {
+                            Ok(event) if event.tx_hash == hash => return Ok(Some(event)),
+                            Ok(_) => continue,
+                            // the missed events may include the ballot
+                            Err(RecvError::Lagged(_)) => {
+                                let mut connection = state.pool.acquire().await?;
+                                let stored = crate::db::get_ballot_by_tx_hash(&mut connection, &hash).await?;
+                                if stored.is_some() {
+                                    return Ok(None);
+                                }
+                            }
+                            Err(e) => return Err(Error::from(e)),
+                        }
+                    }
+                })
+                .await;
+                // on timeout, the ballot may still be in a block whose event was missed
+                if let Some(error) = event.unwrap_or(Ok(None))?.and_then(|event| event.error) {
+                    anyhow::bail!(error);
+                }
+                let mut connection = state.pool.acquire().await?;
+                let (election, height, block_height) =
+                    crate::db::get_ballot_by_tx_hash(&mut connection, &hash)
+                        .await?
+                        .ok_or(anyhow::anyhow!("Ballot {hash} not committed yet"))?;
+                Ok(BallotResponse::Committed(Json(TxStatus::Committed {
+                    election,
+                    height,
+                    block_height,
+                })))
+            }
+            "queue" => {
+                let mut connection = state.pool.acquire().await?;
+                let queue_id = enqueue(&mut connection, id, &tx_bytes).await?;
+                let status = queue_status(&mut connection, queue_id)
+                    .await?
+                    .ok_or(anyhow::anyhow!("Ballot {queue_id} was not queued"))?;
+                Ok(BallotResponse::Queued(Json(status)))
+            }
+            _ => unreachable!(),
         }
-        let result = &json_rep.pointer("/result/hash")
-            .map(|v| v.as_str().unwrap().to_string()).unwrap_or_default();
-
-        Ok::<_, Error>(result.clone())
     };
     res.await.map_err(|e| Custom(Status::InternalServerError, e.to_string()))
 }
+
+/// Status of a ballot submitted with `wait=queue`, by its queue id
+#[rocket::get("/queue/<id>")]
+pub async fn get_queue_status(
+    id: u32,
+    state: &State<Context>,
+) -> Result<Json<QueueStatus>, Custom<String>> {
+    let res = async {
+        let mut connection = state.pool.acquire().await?;
+        queue_status(&mut connection, id).await
+    };
+    match res.await {
+        Ok(Some(status)) => Ok(Json(status)),
+        Ok(None) => Err(Custom(Status::NotFound, format!("No queued ballot {id}"))),
+        Err(e) => Err(Custom(Status::InternalServerError, e.to_string())),
+    }
+}
+
+/// The token of an `Authorization: Bearer <token>` header
+pub struct BearerToken(Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for BearerToken {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let token = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|h| h.strip_prefix("Bearer "))
+            .map(str::to_string);
+        Outcome::Success(BearerToken(token))
+    }
+}
+
+impl BearerToken {
+    /// Compares in constant time
+    fn matches(&self, expected: &str) -> bool {
+        let Some(token) = self.0.as_ref() else {
+            return false;
+        };
+        token.len() == expected.len()
+            && token
+                .bytes()
+                .zip(expected.bytes())
+                .fold(0, |acc, (a, b)| acc | (a ^ b))
+                == 0
+    }
+}
+
+/// Back up the database into `backup_dir` after the current block.
+/// Only accepted from the local host with the `admin_token` of the config.
+#[rocket::post("/admin/backup")]
+pub async fn post_backup(
+    remote: SocketAddr,
+    token: BearerToken,
+    state: &State<Context>,
+    chain: &State<VoteChain>,
+) -> Result<Json<BackupInfo>, Custom<String>> {
+    let authorized = state.admin_token.as_ref().is_some_and(|t| token.matches(t));
+    if !remote.ip().is_loopback() || !authorized {
+        return Err(Custom(Status::Forbidden, "Forbidden".to_string()));
+    }
+    let Some(dir) = state.backup_dir.clone() else {
+        return Err(Custom(Status::NotFound, "backup_dir is not configured".to_string()));
+    };
+    let chain = chain.inner().clone();
+    let res = rocket::tokio::task::spawn_blocking(move || chain.backup(&dir))
+        .await
+        .map_err(Error::msg)
+        .and_then(|r| r);
+    res.map(Json).map_err(|e| Custom(Status::InternalServerError, e.to_string()))
+}
@@ -0,0 +1,190 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::Result;
+use base64::{prelude::BASE64_STANDARD, Engine as _};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+fn default_timeout() -> u64 {
+    30
+}
+
+/// A CometBFT RPC endpoint, from `custom.rpc` in `Rocket.toml`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RpcEndpoint {
+    pub url: String,
+    /// seconds
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+}
+
+/// How requests are spread over the healthy endpoints
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RpcStrategy {
+    /// the first healthy endpoint in the configured order
+    #[default]
+    Failover,
+    RoundRobin,
+}
+
+/// A tx rejected by check_tx
+#[derive(Debug)]
+pub struct CheckTxError(pub String);
+
+impl std::fmt::Display for CheckTxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for CheckTxError {}
+
+/// JSON-RPC client of the CometBFT nodes.
+/// A request goes to the next endpoint when an endpoint cannot be reached.
+pub struct CometRpc {
+    endpoints: Vec<(RpcEndpoint, AtomicBool)>,
+    strategy: RpcStrategy,
+    next: AtomicUsize,
+    client: reqwest::Client,
+}
+
+impl CometRpc {
+    /// The local node, whose RPC port is the ABCI port - 1
+    pub fn new(comet_bft: u16) -> Self {
+        let rpc_port = comet_bft.saturating_sub(1);
+        Self::with_url(format!("http://127.0.0.1:{rpc_port}/v1"))
+    }
+
+    pub fn with_url(url: String) -> Self {
+        let endpoint = RpcEndpoint {
+            url,
+            timeout: default_timeout(),
+        };
+        Self::with_endpoints(vec![endpoint], RpcStrategy::Failover)
+    }
+
+    pub fn with_endpoints(endpoints: Vec<RpcEndpoint>, strategy: RpcStrategy) -> Self {
+        Self {
+            endpoints: endpoints
+                .into_iter()
+                .map(|e| (e, AtomicBool::new(true)))
+                .collect(),
+            strategy,
+            next: AtomicUsize::new(0),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Url and health of every endpoint
+    pub fn health(&self) -> Vec<(String, bool)> {
+        self.endpoints
+            .iter()
+            .map(|(e, healthy)| (e.url.clone(), healthy.load(Ordering::Relaxed)))
+            .collect()
+    }
+
+    /// Endpoints in the order they are tried: the healthy ones first,
+    /// starting from the next one for round robin
+    fn order(&self) -> Vec<usize> {
+        let n = self.endpoints.len();
+        let start = match self.strategy {
+            RpcStrategy::Failover => 0,
+            RpcStrategy::RoundRobin => self.next.fetch_add(1, Ordering::Relaxed) % n.max(1),
+        };
+        let (mut healthy, unhealthy): (Vec<_>, Vec<_>) = (0..n)
+            .map(|i| (start + i) % n)
+            .partition(|i| self.endpoints[*i].1.load(Ordering::Relaxed));
+        healthy.extend(unhealthy);
+        healthy
+    }
+
+    async fn post(&self, endpoint: &RpcEndpoint, body: &Value) -> reqwest::Result<Value> {
+        let rep = self.client.post(&endpoint.url)
+            .timeout(Duration::from_secs(endpoint.timeout))
+            .json(body).send().await?.error_for_status()?;
+        rep.json().await
+    }
+
+    async fn call(&self, method: &str, params: Value) -> Result<Value> {
+        let req_body = serde_json::json!({
+            "id": "",
+            "method": method,
+            "params": params
+        });
+        let mut error = anyhow::anyhow!("No RPC endpoint");
+        for i in self.order() {
+            let (endpoint, healthy) = &self.endpoints[i];
+            tracing::info!("{method} to {}", endpoint.url);
+            match self.post(endpoint, &req_body).await {
+                Ok(json_rep) => {
+                    healthy.store(true, Ordering::Relaxed);
+                    tracing::info!("{method} rep: {:?}", json_rep);
+                    // the node answered: its errors are not retried elsewhere
+                    if let Some(error_msg) = json_rep.pointer("/error/data") {
+                        anyhow::bail!(error_msg.as_str().unwrap_or_default().to_string());
+                    }
+                    return Ok(json_rep["result"].clone());
+                }
+                Err(e) => {
+                    tracing::warn!("{method} to {} failed: {e}", endpoint.url);
+                    healthy.store(false, Ordering::Relaxed);
+                    error = e.into();
+                }
+            }
+        }
+        Err(error)
+    }
+
+    /// Call `health` on every endpoint and update their status
+    pub async fn check_health(&self) {
+        let req_body = serde_json::json!({
+            "id": "",
+            "method": "health",
+            "params": {}
+        });
+        for (endpoint, healthy) in self.endpoints.iter() {
+            let ok = self.post(endpoint, &req_body).await.is_ok();
+            if ok != healthy.swap(ok, Ordering::Relaxed) {
+                tracing::warn!("RPC endpoint {} is {}", endpoint.url, if ok { "up" } else { "down" });
+            }
+        }
+    }
+
+    /// Check the endpoints every `interval` seconds
+    pub fn spawn_health_checks(self: Arc<Self>, interval: u64) {
+        rocket::tokio::spawn(async move {
+            loop {
+                self.check_health().await;
+                rocket::tokio::time::sleep(Duration::from_secs(interval)).await;
+            }
+        });
+    }
+
+    /// Returns the tx hash once check_tx has passed
+    pub async fn broadcast_tx_sync(&self, tx: &[u8]) -> Result<String> {
+        self.broadcast_tx("broadcast_tx_sync", tx).await
+    }
+
+    /// Returns the tx hash without waiting for check_tx
+    pub async fn broadcast_tx_async(&self, tx: &[u8]) -> Result<String> {
+        self.broadcast_tx("broadcast_tx_async", tx).await
+    }
+
+    async fn broadcast_tx(&self, method: &str, tx: &[u8]) -> Result<String> {
+        let tx_data = BASE64_STANDARD.encode(tx);
+        let result = self.call(method, serde_json::json!([tx_data])).await?;
+        // broadcast_tx_sync reports check_tx failures in the result
+        if result["code"].as_u64().unwrap_or_default() != 0 {
+            let error = result["data"].as_str().unwrap_or_default().to_string();
+            return Err(CheckTxError(error).into());
+        }
+        Ok(result["hash"].as_str().unwrap_or_default().to_string())
+    }
+}
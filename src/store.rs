@@ -0,0 +1,472 @@
+use std::collections::{BTreeMap, HashSet};
+
+use anyhow::Result;
+use orchard::vote::{Ballot, Frontier};
+use sqlx::{sqlite::SqliteRow, Row, SqliteConnection};
+use zcash_vote::{
+    db::{load_prop, store_prop},
+    election::Election,
+};
+
+use crate::db::{
+    check_cmx_root, decode_frontier, encode_frontier, get_election, get_num_ballots, store_ballot,
+    AppState, BlockRef,
+};
+
+/// State operations of the vote chain
+///
+/// The writes of a block go in one transaction, from `begin` to `commit`.
+/// Each ballot is written in a savepoint, so that a rejected ballot leaves
+/// no write in the block. `begin` discards a block that was not committed.
+#[allow(async_fn_in_trait)]
+pub trait VoteStore {
+    async fn get_election(&mut self, id: &str) -> Result<(u32, String, bool)>;
+    async fn check_cmx_root(&mut self, id_election: u32, cmx: &[u8]) -> Result<()>;
+    async fn nullifier_exists(&mut self, id_election: u32, nf: &[u8]) -> Result<bool>;
+    /// Fails if the nullifier was already spent. `height` is the ballot height
+    async fn store_nullifier(&mut self, id_election: u32, nf: &[u8], height: u32) -> Result<()>;
+    async fn frontier_height(&mut self, id_election: u32) -> Result<u32>;
+    async fn frontier_at(&mut self, id_election: u32, height: u32) -> Result<Frontier>;
+    async fn store_frontier(&mut self, id_election: u32, height: u32, frontier: &Frontier) -> Result<()>;
+    async fn num_ballots(&mut self, id_election: u32) -> Result<u32>;
+    /// Store the ballot at the next height of the election with the new cmx root
+    async fn append_ballot(&mut self, id_election: u32, ballot: &Ballot, cmx_root: &[u8], block: &BlockRef) -> Result<u32>;
+    /// Latest cmx root of every election with its id, ordered by election id
+    async fn latest_cmx_roots(&mut self) -> Result<Vec<(String, Vec<u8>)>>;
+    async fn app_state(&mut self) -> Result<AppState>;
+    async fn store_app_state(&mut self, app_state: &AppState) -> Result<()>;
+    /// Record the app state of a committed block in the history
+    async fn record_app_state(&mut self, app_state: &AppState, time: i64) -> Result<()>;
+    async fn begin(&mut self) -> Result<()>;
+    /// Start the writes of a ballot
+    async fn savepoint(&mut self) -> Result<()>;
+    /// Keep the writes of the ballot in the block
+    async fn release_savepoint(&mut self) -> Result<()>;
+    /// Discard the writes of the ballot
+    async fn rollback_to_savepoint(&mut self) -> Result<()>;
+    async fn commit(&mut self) -> Result<()>;
+    /// Write a copy of the committed state to a new database file
+    async fn backup(&mut self, path: &str) -> Result<()>;
+}
+
+impl VoteStore for SqliteConnection {
+    async fn get_election(&mut self, id: &str) -> Result<(u32, String, bool)> {
+        get_election(self, id).await
+    }
+
+    async fn check_cmx_root(&mut self, id_election: u32, cmx: &[u8]) -> Result<()> {
+        check_cmx_root(self, id_election, cmx).await
+    }
+
+    async fn nullifier_exists(&mut self, id_election: u32, nf: &[u8]) -> Result<bool> {
+        let exists = sqlx::query("SELECT 1 FROM dnfs WHERE election = ?1 AND hash = ?2")
+            .bind(id_election)
+            .bind(nf)
+            .fetch_optional(&mut *self)
+            .await?
+            .is_some();
+        Ok(exists)
+    }
+
+    async fn store_nullifier(&mut self, id_election: u32, nf: &[u8], height: u32) -> Result<()> {
+        sqlx::query("INSERT INTO dnfs(election, hash, height) VALUES (?1, ?2, ?3)")
+            .bind(id_election)
+            .bind(nf)
+            .bind(height)
+            .execute(&mut *self)
+            .await?;
+        Ok(())
+    }
+
+    async fn frontier_height(&mut self, id_election: u32) -> Result<u32> {
+        let (height,): (u32,) =
+            sqlx::query_as("SELECT MAX(height) FROM cmx_frontiers WHERE election = ?1")
+                .bind(id_election)
+                .fetch_one(&mut *self)
+                .await?;
+        Ok(height)
+    }
+
+    async fn frontier_at(&mut self, id_election: u32, height: u32) -> Result<Frontier> {
+        let frontier = sqlx::query(
+            "SELECT frontier FROM cmx_frontiers WHERE election = ?1 AND height = ?2")
+            .bind(id_election).bind(height)
+            .map(|r: SqliteRow| {
+                let cmx_frontier: Vec<u8> = r.get(0);
+                decode_frontier(&cmx_frontier)
+            }).fetch_one(&mut *self).await??;
+        Ok(frontier)
+    }
+
+    async fn store_frontier(&mut self, id_election: u32, height: u32, frontier: &Frontier) -> Result<()> {
+        let frontier = encode_frontier(frontier)?;
+        sqlx::query(
+            "INSERT INTO cmx_frontiers(election, height, frontier)
+            VALUES (?1, ?2, ?3)",
+        )
+        .bind(id_election)
+        .bind(height)
+        .bind(&frontier)
+        .execute(&mut *self)
+        .await?;
+        Ok(())
+    }
+
+    async fn num_ballots(&mut self, id_election: u32) -> Result<u32> {
+        get_num_ballots(self, id_election).await
+    }
+
+    async fn append_ballot(&mut self, id_election: u32, ballot: &Ballot, cmx_root: &[u8], block: &BlockRef) -> Result<u32> {
+        let height = get_num_ballots(self, id_election).await?;
+        tracing::info!("ballot height: {height}");
+        store_ballot(self, id_election, height + 1, ballot, cmx_root, Some(block)).await
+    }
+
+    async fn latest_cmx_roots(&mut self) -> Result<Vec<(String, Vec<u8>)>> {
+        let hashes = sqlx::query(
+            "SELECT e.id, t1.hash
+            FROM cmx_roots t1
+            JOIN (
+                SELECT election, MAX(height) AS max_height
+                FROM cmx_roots
+                GROUP BY election
+            ) t2
+            ON t1.election = t2.election AND t1.height = t2.max_height
+            JOIN elections e ON t1.election = e.id_election
+            ORDER BY e.id"
+        )
+        .map(|r: SqliteRow| {
+            let id: String = r.get(0);
+            let hash: Vec<u8> = r.get(1);
+            (id, hash)
+        })
+        .fetch_all(&mut *self)
+        .await?;
+        Ok(hashes)
+    }
+
+    async fn app_state(&mut self) -> Result<AppState> {
+        let s = load_prop(self, "state").await?.unwrap();
+        Ok(serde_json::from_str::<AppState>(&s)?)
+    }
+
+    async fn store_app_state(&mut self, app_state: &AppState) -> Result<()> {
+        store_prop(self, "state", &serde_json::to_string(app_state).unwrap()).await?;
+        Ok(())
+    }
+
+    async fn record_app_state(&mut self, app_state: &AppState, time: i64) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO app_states(height, hash, time, last_ballot, elections)
+            SELECT ?1, ?2, ?3, COALESCE(MAX(id_ballot), 0), ?4 FROM ballots WHERE TRUE
+            ON CONFLICT DO UPDATE SET
+            hash = excluded.hash,
+            time = excluded.time,
+            last_ballot = excluded.last_ballot,
+            elections = excluded.elections",
+        )
+        .bind(app_state.height)
+        .bind(&app_state.hash)
+        .bind(time)
+        .bind(serde_json::to_string(&app_state.elections)?)
+        .execute(&mut *self)
+        .await?;
+        Ok(())
+    }
+
+    async fn begin(&mut self) -> Result<()> {
+        let _ = sqlx::query("ROLLBACK").execute(&mut *self).await;
+        sqlx::query("BEGIN TRANSACTION").execute(&mut *self).await?;
+        Ok(())
+    }
+
+    async fn savepoint(&mut self) -> Result<()> {
+        sqlx::query("SAVEPOINT ballot").execute(&mut *self).await?;
+        Ok(())
+    }
+
+    async fn release_savepoint(&mut self) -> Result<()> {
+        sqlx::query("RELEASE ballot").execute(&mut *self).await?;
+        Ok(())
+    }
+
+    async fn rollback_to_savepoint(&mut self) -> Result<()> {
+        // ROLLBACK TO keeps the savepoint open
+        sqlx::query("ROLLBACK TO ballot").execute(&mut *self).await?;
+        sqlx::query("RELEASE ballot").execute(&mut *self).await?;
+        Ok(())
+    }
+
+    async fn commit(&mut self) -> Result<()> {
+        let _ = sqlx::query("COMMIT").execute(&mut *self).await;
+        Ok(())
+    }
+
+    async fn backup(&mut self, path: &str) -> Result<()> {
+        sqlx::query("VACUUM INTO ?1").bind(path).execute(&mut *self).await?;
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct MemoryState {
+    // id, definition, closed. id_election is the index + 1
+    elections: Vec<(String, String, bool)>,
+    // election, height, ballot. id_ballot is the index + 1
+    ballots: Vec<(u32, u32, Ballot)>,
+    frontiers: BTreeMap<(u32, u32), Frontier>,
+    roots: BTreeMap<(u32, u32), Vec<u8>>,
+    dnfs: HashSet<(u32, Vec<u8>)>,
+    app_state: AppState,
+    // app state, time and last id_ballot of every committed block
+    app_states: BTreeMap<u32, (AppState, i64, u32)>,
+}
+
+/// A write of the current block, with what it takes to revert it
+enum Undo {
+    Nullifier(u32, Vec<u8>),
+    Frontier(u32, u32),
+    // election and id_ballot of the last ballot
+    Ballot(u32, u32),
+    AppState(AppState),
+    RecordedAppState(u32, Option<(AppState, i64, u32)>),
+}
+
+/// In memory store for tests and simulations
+pub struct MemoryStore {
+    state: MemoryState,
+    // writes since the start of the block, if a block is open
+    undo_log: Option<Vec<Undo>>,
+    // length of the undo log at the start of the ballot
+    savepoint: Option<usize>,
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self {
+            state: MemoryState {
+                app_state: AppState::initial(),
+                ..MemoryState::default()
+            },
+            undo_log: None,
+            savepoint: None,
+        }
+    }
+
+    fn log(&mut self, undo: Undo) {
+        if let Some(undo_log) = self.undo_log.as_mut() {
+            undo_log.push(undo);
+        }
+    }
+
+    /// Revert the writes of the block after the first `len`
+    fn undo(&mut self, len: usize) {
+        let Some(undo_log) = self.undo_log.as_mut() else {
+            return;
+        };
+        let state = &mut self.state;
+        for undo in undo_log.drain(len..).rev() {
+            match undo {
+                Undo::Nullifier(id_election, nf) => {
+                    state.dnfs.remove(&(id_election, nf));
+                }
+                Undo::Frontier(id_election, height) => {
+                    state.frontiers.remove(&(id_election, height));
+                }
+                Undo::Ballot(id_election, id_ballot) => {
+                    state.ballots.pop();
+                    state.roots.remove(&(id_election, id_ballot));
+                }
+                Undo::AppState(app_state) => state.app_state = app_state,
+                Undo::RecordedAppState(height, previous) => match previous {
+                    Some(previous) => {
+                        state.app_states.insert(height, previous);
+                    }
+                    None => {
+                        state.app_states.remove(&height);
+                    }
+                },
+            }
+        }
+    }
+
+    pub fn add_election(&mut self, election: &Election, closed: bool) -> Result<u32> {
+        let definition = serde_json::to_string(election)?;
+        let state = &mut self.state;
+        let id_election = match state.elections.iter().position(|(id, _, _)| *id == election.id()) {
+            Some(i) => {
+                state.elections[i] = (election.id(), definition, closed);
+                i as u32 + 1
+            }
+            None => {
+                state.elections.push((election.id(), definition, closed));
+                state.elections.len() as u32
+            }
+        };
+        let cmx_frontier = election.cmx_frontier.clone().unwrap();
+        state.roots.entry((id_election, 0)).or_insert(cmx_frontier.root().to_vec());
+        state.frontiers.entry((id_election, 0)).or_insert(cmx_frontier);
+        Ok(id_election)
+    }
+
+    pub fn ballots(&self, id_election: u32) -> Vec<&Ballot> {
+        self.state
+            .ballots
+            .iter()
+            .filter(|(e, _, _)| *e == id_election)
+            .map(|(_, _, b)| b)
+            .collect()
+    }
+}
+
+impl VoteStore for MemoryStore {
+    async fn get_election(&mut self, id: &str) -> Result<(u32, String, bool)> {
+        let (i, (_, definition, closed)) = self
+            .state
+            .elections
+            .iter()
+            .enumerate()
+            .find(|(_, (e, _, _))| e == id)
+            .ok_or(anyhow::anyhow!("Unknown election {id}"))?;
+        Ok((i as u32 + 1, definition.clone(), *closed))
+    }
+
+    async fn check_cmx_root(&mut self, id_election: u32, cmx: &[u8]) -> Result<()> {
+        self.state
+            .roots
+            .range((id_election, 0)..=(id_election, u32::MAX))
+            .find(|(_, root)| root.as_slice() == cmx)
+            .ok_or(anyhow::anyhow!("Invalid cmx root"))?;
+        Ok(())
+    }
+
+    async fn nullifier_exists(&mut self, id_election: u32, nf: &[u8]) -> Result<bool> {
+        Ok(self.state.dnfs.contains(&(id_election, nf.to_vec())))
+    }
+
+    async fn store_nullifier(&mut self, id_election: u32, nf: &[u8], _height: u32) -> Result<()> {
+        if !self.state.dnfs.insert((id_election, nf.to_vec())) {
+            anyhow::bail!("Duplicate nullifier");
+        }
+        self.log(Undo::Nullifier(id_election, nf.to_vec()));
+        Ok(())
+    }
+
+    async fn frontier_height(&mut self, id_election: u32) -> Result<u32> {
+        let ((_, height), _) = self
+            .state
+            .frontiers
+            .range((id_election, 0)..=(id_election, u32::MAX))
+            .next_back()
+            .ok_or(anyhow::anyhow!("No cmx frontier"))?;
+        Ok(*height)
+    }
+
+    async fn frontier_at(&mut self, id_election: u32, height: u32) -> Result<Frontier> {
+        let frontier = self
+            .state
+            .frontiers
+            .get(&(id_election, height))
+            .ok_or(anyhow::anyhow!("No cmx frontier at height {height}"))?;
+        Ok(frontier.clone())
+    }
+
+    async fn store_frontier(&mut self, id_election: u32, height: u32, frontier: &Frontier) -> Result<()> {
+        if self.state.frontiers.contains_key(&(id_election, height)) {
+            anyhow::bail!("Duplicate cmx frontier");
+        }
+        self.state.frontiers.insert((id_election, height), frontier.clone());
+        self.log(Undo::Frontier(id_election, height));
+        Ok(())
+    }
+
+    async fn num_ballots(&mut self, id_election: u32) -> Result<u32> {
+        Ok(self.ballots(id_election).len() as u32)
+    }
+
+    async fn append_ballot(&mut self, id_election: u32, ballot: &Ballot, cmx_root: &[u8], _block: &BlockRef) -> Result<u32> {
+        let height = self.num_ballots(id_election).await? + 1;
+        self.state.ballots.push((id_election, height, ballot.clone()));
+        let id_ballot = self.state.ballots.len() as u32;
+        self.state.roots.insert((id_election, id_ballot), cmx_root.to_vec());
+        self.log(Undo::Ballot(id_election, id_ballot));
+        Ok(id_ballot)
+    }
+
+    async fn latest_cmx_roots(&mut self) -> Result<Vec<(String, Vec<u8>)>> {
+        let mut elections = self.state.elections.iter().enumerate().collect::<Vec<_>>();
+        elections.sort_by(|(_, (a, _, _)), (_, (b, _, _))| a.cmp(b));
+        let roots = elections
+            .into_iter()
+            .filter_map(|(i, (id, _, _))| {
+                let id_election = i as u32 + 1;
+                self.state
+                    .roots
+                    .range((id_election, 0)..=(id_election, u32::MAX))
+                    .next_back()
+                    .map(|(_, root)| (id.clone(), root.clone()))
+            })
+            .collect();
+        Ok(roots)
+    }
+
+    async fn app_state(&mut self) -> Result<AppState> {
+        Ok(self.state.app_state.clone())
+    }
+
+    async fn store_app_state(&mut self, app_state: &AppState) -> Result<()> {
+        let previous = std::mem::replace(&mut self.state.app_state, app_state.clone());
+        self.log(Undo::AppState(previous));
+        Ok(())
+    }
+
+    async fn record_app_state(&mut self, app_state: &AppState, time: i64) -> Result<()> {
+        let last_ballot = self.state.ballots.len() as u32;
+        let previous = self
+            .state
+            .app_states
+            .insert(app_state.height, (app_state.clone(), time, last_ballot));
+        self.log(Undo::RecordedAppState(app_state.height, previous));
+        Ok(())
+    }
+
+    async fn begin(&mut self) -> Result<()> {
+        self.undo(0);
+        self.undo_log = Some(vec![]);
+        self.savepoint = None;
+        Ok(())
+    }
+
+    async fn savepoint(&mut self) -> Result<()> {
+        self.savepoint = self.undo_log.as_ref().map(Vec::len);
+        Ok(())
+    }
+
+    async fn release_savepoint(&mut self) -> Result<()> {
+        self.savepoint = None;
+        Ok(())
+    }
+
+    async fn rollback_to_savepoint(&mut self) -> Result<()> {
+        if let Some(len) = self.savepoint.take() {
+            self.undo(len);
+        }
+        Ok(())
+    }
+
+    async fn commit(&mut self) -> Result<()> {
+        self.undo_log = None;
+        self.savepoint = None;
+        Ok(())
+    }
+
+    async fn backup(&mut self, _path: &str) -> Result<()> {
+        anyhow::bail!("The memory store cannot be backed up")
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::{BTreeMap, HashSet};
+
+use anyhow::Result;
+use orchard::{
+    keys::{FullViewingKey, PreparedIncomingViewingKey, Scope, SpendingKey},
+    note::{ExtractedNoteCommitment, Nullifier},
+    note_encryption::{CompactAction, OrchardDomain},
+    vote::BallotAction,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::SqliteConnection;
+use zcash_address::unified::{self, Container, Encoding};
+use zcash_note_encryption::{try_compact_note_decryption, EphemeralKeyBytes, COMPACT_NOTE_SIZE};
+use zcash_vote::{as_byte256, election::Election};
+
+use crate::{
+    db::{get_ballot_height, get_election, get_num_ballots},
+    decryption::{ivk_scalar, prove, verify, DecryptionProof},
+};
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TallyOutput {
+    pub height: u32,
+    pub sighash: String,
+    pub action: usize,
+    pub cmx: String,
+    pub candidate: Option<usize>,
+    pub address: String,
+    pub amount: u64,
+    pub proof: DecryptionProof,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CandidateTally {
+    pub candidate: usize,
+    pub choice: String,
+    pub address: String,
+    pub amount: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Tally {
+    pub election: String,
+    pub ballots: u32,
+    pub candidates: Vec<CandidateTally>,
+    pub outputs: Vec<TallyOutput>,
+}
+
+pub fn parse_spending_key(key: &str) -> Result<SpendingKey> {
+    let key = hex::decode(key)?;
+    let key: [u8; 32] = key
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Election key must be 32 bytes"))?;
+    let sk: Option<SpendingKey> = SpendingKey::from_bytes(key).into();
+    sk.ok_or(anyhow::anyhow!("Invalid election key"))
+}
+
+/// Orchard receiver of a candidate unified address
+pub fn orchard_receiver(address: &str) -> Option<[u8; 43]> {
+    let (_, ua) = unified::Address::decode(address).ok()?;
+    ua.items().into_iter().find_map(|r| match r {
+        unified::Receiver::Orchard(receiver) => Some(receiver),
+        _ => None,
+    })
+}
+
+pub async fn tally(connection: &mut SqliteConnection, id: &str, sk: &SpendingKey) -> Result<Tally> {
+    let (id_election, election, _) = get_election(connection, id).await?;
+    let election = serde_json::from_str::<Election>(&election)?;
+    let fvk = FullViewingKey::from(sk);
+    let ivk = fvk.to_ivk(Scope::External);
+    let ivk_scalar = ivk_scalar(&ivk)?;
+    let ivk = PreparedIncomingViewingKey::new(&ivk);
+
+    let receivers = election
+        .candidates
+        .iter()
+        .map(|c| orchard_receiver(&c.address))
+        .collect::<Vec<_>>();
+    let mut amounts = BTreeMap::<usize, u64>::new();
+    let mut outputs = vec![];
+
+    let n = get_num_ballots(connection, id_election).await?;
+    for height in 1..=n {
+        let ballot = get_ballot_height(connection, id_election, height).await?;
+        let sighash = hex::encode(ballot.data.sighash()?);
+        for (i, action) in ballot.data.actions.iter().enumerate() {
+            let Some((address, amount)) = decrypt_action(&ivk, action)? else {
+                continue;
+            };
+            let proof = prove(&ivk_scalar, &address, &action.epk)?;
+            let candidate = receivers.iter().position(|r| r.as_ref() == Some(&address));
+            if let Some(candidate) = candidate {
+                *amounts.entry(candidate).or_default() += amount;
+            }
+            outputs.push(TallyOutput {
+                height,
+                sighash: sighash.clone(),
+                action: i,
+                cmx: hex::encode(&action.cmx),
+                candidate,
+                address: hex::encode(address),
+                amount,
+                proof,
+            });
+        }
+    }
+
+    let candidates = election
+        .candidates
+        .iter()
+        .enumerate()
+        .map(|(i, c)| CandidateTally {
+            candidate: i,
+            choice: c.choice.clone(),
+            address: c.address.clone(),
+            amount: amounts.get(&i).copied().unwrap_or_default(),
+        })
+        .collect();
+
+    Ok(Tally {
+        election: election.id(),
+        ballots: n,
+        candidates,
+        outputs,
+    })
+}
+
+/// Trial decrypt a ballot output, returning its raw recipient address and value
+pub fn decrypt_action(
+    ivk: &PreparedIncomingViewingKey,
+    action: &BallotAction,
+) -> Result<Option<([u8; 43], u64)>> {
+    let nf: Option<Nullifier> = Nullifier::from_bytes(&as_byte256(&action.nf)).into();
+    let cmx: Option<ExtractedNoteCommitment> =
+        ExtractedNoteCommitment::from_bytes(&as_byte256(&action.cmx)).into();
+    let (Some(nf), Some(cmx)) = (nf, cmx) else {
+        anyhow::bail!("Invalid ballot action");
+    };
+    if action.enc.len() < COMPACT_NOTE_SIZE {
+        anyhow::bail!("Invalid note ciphertext");
+    }
+    let enc: [u8; COMPACT_NOTE_SIZE] = action.enc[0..COMPACT_NOTE_SIZE].try_into().unwrap();
+    let compact = CompactAction::from_parts(nf, cmx, EphemeralKeyBytes(as_byte256(&action.epk)), enc);
+    let domain = OrchardDomain::for_nullifier(nf);
+    let r = try_compact_note_decryption(&domain, ivk, &compact)
+        .map(|(note, address)| (address.to_raw_address_bytes(), note.value().inner()));
+    Ok(r)
+}
+
+/// Check the decryption proofs of a published tally against the ballots
+/// and recompute the totals. Returns every discrepancy found.
+pub async fn verify_tally(connection: &mut SqliteConnection, tally: &Tally) -> Result<Vec<String>> {
+    let (id_election, election, _) = get_election(connection, &tally.election).await?;
+    let election = serde_json::from_str::<Election>(&election)?;
+    let receivers = election
+        .candidates
+        .iter()
+        .map(|c| orchard_receiver(&c.address))
+        .collect::<Vec<_>>();
+
+    let mut errors = vec![];
+    let n = get_num_ballots(connection, id_election).await?;
+    if n != tally.ballots {
+        errors.push(format!("Ballots: {} != {}", n, tally.ballots));
+    }
+
+    let mut amounts = BTreeMap::<usize, u64>::new();
+    let mut seen = HashSet::<(u32, usize)>::new();
+    for o in tally.outputs.iter() {
+        if !seen.insert((o.height, o.action)) {
+            errors.push(format!("Ballot {} action {}: Duplicate output", o.height, o.action));
+            continue;
+        }
+        let res = async {
+            let ballot = get_ballot_height(connection, id_election, o.height).await?;
+            let action = ballot
+                .data
+                .actions
+                .get(o.action)
+                .ok_or(anyhow::anyhow!("Unknown action"))?;
+            if hex::encode(&action.cmx) != o.cmx {
+                anyhow::bail!("cmx mismatch");
+            }
+            let address: [u8; 43] = hex::decode(&o.address)?
+                .try_into()
+                .map_err(|_| anyhow::anyhow!("Invalid address"))?;
+            let amount = verify(&o.proof, &address, action)?;
+            if amount != o.amount {
+                anyhow::bail!("Amount mismatch: {amount} != {}", o.amount);
+            }
+            let candidate = receivers.iter().position(|r| r.as_ref() == Some(&address));
+            if candidate != o.candidate {
+                anyhow::bail!("Candidate mismatch");
+            }
+            if let Some(candidate) = candidate {
+                *amounts.entry(candidate).or_default() += amount;
+            }
+            Ok::<_, anyhow::Error>(())
+        };
+        if let Err(e) = res.await {
+            errors.push(format!("Ballot {} action {}: {}", o.height, o.action, e));
+        }
+    }
+
+    for c in tally.candidates.iter() {
+        let amount = amounts.get(&c.candidate).copied().unwrap_or_default();
+        if amount != c.amount {
+            errors.push(format!(
+                "Candidate {}: total {} != {}",
+                c.candidate, amount, c.amount
+            ));
+        }
+    }
+
+    Ok(errors)
+}
+
+pub fn to_csv(tally: &Tally) -> String {
+    let mut csv = "height,sighash,action,cmx,candidate,address,amount\n".to_string();
+    for o in tally.outputs.iter() {
+        let candidate = o.candidate.map(|c| c.to_string()).unwrap_or_default();
+        csv.push_str(&format!(
+            "{},{},{},{},{},{},{}\n",
+            o.height, o.sighash, o.action, o.cmx, candidate, o.address, o.amount
+        ));
+    }
+    csv
+}
@@ -0,0 +1,150 @@
+use anyhow::Result;
+use incrementalmerkletree::{Altitude, Hashable};
+use orchard::{tree::MerkleHashOrchard, vote::Frontier};
+use serde::{Deserialize, Serialize};
+use sqlx::SqliteConnection;
+use zcash_vote::as_byte256;
+
+use crate::{
+    db::{get_cmx_leaf, get_cmx_leaf_index, get_cmx_node, get_cmx_root_at, get_num_ballots},
+    store::VoteStore,
+};
+
+const DEPTH: u8 = 32;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Witness {
+    pub cmx: String,
+    pub position: u64,
+    pub anchor_height: u32,
+    pub anchor: String,
+    /// sibling hashes from the leaf up to the root
+    pub path: Vec<String>,
+}
+
+/// Authentication path of a ballot output against the cmx root after
+/// `anchor_height` ballots, or the latest root.
+/// Complete siblings are read from the cmx nodes, or the ommers of the
+/// initial frontier. The sibling that covers the last leaf at the anchor
+/// is computed from the frontier at the anchor.
+pub async fn witness(
+    connection: &mut SqliteConnection,
+    id_election: u32,
+    cmx: &[u8],
+    anchor_height: Option<u32>,
+) -> Result<Witness> {
+    let num_ballots = get_num_ballots(connection, id_election).await?;
+    let anchor_height = anchor_height.unwrap_or(num_ballots);
+    if anchor_height > num_ballots {
+        anyhow::bail!("Anchor height {anchor_height} is above {num_ballots}");
+    }
+    let index = get_cmx_leaf_index(connection, id_election, cmx, anchor_height)
+        .await?
+        .ok_or(anyhow::anyhow!("cmx not found up to height {anchor_height}"))?;
+    let initial = connection.frontier_at(id_election, 0).await?;
+    let anchor_frontier = connection.frontier_at(id_election, anchor_height).await?;
+    let p0 = initial.position as u64;
+    let last = anchor_frontier.position as u64;
+    let position = p0 + 1 + index as u64;
+    let partial = ancestors(&anchor_frontier)?;
+
+    // ommers are ordered by level, for every bit of the position that is set
+    let mut initial_ommers = initial.ommers.iter();
+    let mut path = vec![];
+    for h in 0..DEPTH {
+        let initial_ommer = if (p0 >> h) & 1 == 1 {
+            Some(initial_ommers.next().ok_or(anyhow::anyhow!("Missing ommer at level {h}"))?)
+        } else {
+            None
+        };
+        let sibling = (position >> h) ^ 1;
+        let start = sibling << h;
+        let end = start + (1 << h) - 1;
+        let node = if start > last {
+            MerkleHashOrchard::empty_root(Altitude::from(h)).to_bytes()
+        } else if end > last {
+            partial[h as usize].to_bytes()
+        } else if sibling < p0 >> h {
+            initial_ommer.ok_or(anyhow::anyhow!("No node {sibling} at level {h}"))?.0
+        } else if h == 0 && sibling == p0 {
+            initial.leaf.0
+        } else if h == 0 {
+            as_byte256(&get_cmx_leaf(connection, id_election, (sibling - p0 - 1) as u32).await?)
+        } else {
+            as_byte256(&get_cmx_node(connection, id_election, h, sibling).await?)
+        };
+        path.push(node);
+    }
+
+    let root = fold_path(&as_byte256(cmx), position, &path)?;
+    let anchor = get_cmx_root_at(connection, id_election, anchor_height)
+        .await
+        .map_err(|_| anyhow::anyhow!("No cmx root at height {anchor_height}"))?;
+    if root.as_slice() != anchor {
+        anyhow::bail!("Rebuilt cmx tree does not match the root at height {anchor_height}");
+    }
+
+    Ok(Witness {
+        cmx: hex::encode(cmx),
+        position,
+        anchor_height,
+        anchor: hex::encode(anchor),
+        path: path.iter().map(hex::encode).collect(),
+    })
+}
+
+fn to_hash(b: &[u8; 32]) -> Result<MerkleHashOrchard> {
+    Option::from(MerkleHashOrchard::from_bytes(b)).ok_or(anyhow::anyhow!("Invalid cmx tree node"))
+}
+
+/// Nodes that the last leaf of `frontier` completes, as level, index and hash
+pub fn completed_nodes(frontier: &Frontier) -> Result<Vec<(u8, u64, [u8; 32])>> {
+    let position = frontier.position as u64;
+    let mut node = to_hash(&frontier.leaf.0)?;
+    let mut ommers = frontier.ommers.iter();
+    let mut nodes = vec![];
+    for h in 0..DEPTH {
+        if (position >> h) & 1 == 0 {
+            break;
+        }
+        let ommer = ommers.next().ok_or(anyhow::anyhow!("Missing ommer at level {h}"))?;
+        node = MerkleHashOrchard::combine(Altitude::from(h), &to_hash(&ommer.0)?, &node);
+        nodes.push((h + 1, position >> (h + 1), node.to_bytes()));
+    }
+    Ok(nodes)
+}
+
+/// Ancestors of the last leaf of `frontier` at every level below the root,
+/// with the nodes to its right empty
+fn ancestors(frontier: &Frontier) -> Result<Vec<MerkleHashOrchard>> {
+    let position = frontier.position as u64;
+    let mut node = to_hash(&frontier.leaf.0)?;
+    let mut ommers = frontier.ommers.iter();
+    let mut nodes = vec![node];
+    for h in 0..DEPTH - 1 {
+        let level = Altitude::from(h);
+        node = if (position >> h) & 1 == 1 {
+            let ommer = ommers.next().ok_or(anyhow::anyhow!("Missing ommer at level {h}"))?;
+            MerkleHashOrchard::combine(level, &to_hash(&ommer.0)?, &node)
+        } else {
+            MerkleHashOrchard::combine(level, &node, &MerkleHashOrchard::empty_root(level))
+        };
+        nodes.push(node);
+    }
+    Ok(nodes)
+}
+
+/// Root of the tree from the leaf at `position` and its path
+fn fold_path(leaf: &[u8; 32], position: u64, path: &[[u8; 32]]) -> Result<[u8; 32]> {
+    let mut node = to_hash(leaf)?;
+    for (h, sibling) in path.iter().enumerate() {
+        let level = Altitude::from(h as u8);
+        let sibling = to_hash(sibling)?;
+        node = if (position >> h) & 1 == 0 {
+            MerkleHashOrchard::combine(level, &node, &sibling)
+        } else {
+            MerkleHashOrchard::combine(level, &sibling, &node)
+        };
+    }
+    Ok(node.to_bytes())
+}
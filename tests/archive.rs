@@ -0,0 +1,80 @@
+mod common;
+
+use common::{ballot, other_election, temp_db, tx, Node};
+use zcash_vote_server::{
+    archive::{export_election, import_election},
+    chain::app_hash,
+    context::Context,
+    db::create_schema,
+    fsck::fsck,
+};
+
+#[rocket::async_test]
+async fn export_and_import() {
+    let elections = ["A", "B"].map(other_election).to_vec();
+    let mut node = Node::start(&elections).await;
+    for i in 0..10u8 {
+        let e = &elections[i as usize % 2];
+        node.block(vec![tx(e, ballot(i, i))]);
+    }
+
+    let id = elections[0].id();
+    let mut connection = node.context.pool.acquire().await.unwrap();
+    let archive = export_election(&mut connection, &id).await.unwrap();
+    assert_eq!(archive.manifest.ballots, 5);
+    let data = serde_json::to_string(&archive).unwrap();
+
+    let (_dir, db_path) = temp_db();
+    let context = Context::new(String::new(), db_path, 0).await.unwrap();
+    let mut fresh = context.pool.acquire().await.unwrap();
+    create_schema(&mut fresh).await.unwrap();
+    import_election(&mut fresh, &serde_json::from_str(&data).unwrap())
+        .await
+        .unwrap();
+
+    let imported = export_election(&mut fresh, &id).await.unwrap();
+    assert_eq!(imported.archive.roots, archive.archive.roots);
+    assert_eq!(imported.archive.nullifiers, archive.archive.nullifiers);
+    // the app hash of the fresh database predates the import
+    assert_eq!(fsck(&mut fresh).await.unwrap(), Vec::<String>::new());
+
+    // importing twice fails
+    assert!(import_election(&mut fresh, &archive).await.is_err());
+}
+
+#[rocket::async_test]
+async fn import_rejects_tampered_archive() {
+    let e = other_election("A");
+    let mut node = Node::start(&[e.clone()]).await;
+    for i in 0..3u8 {
+        node.block(vec![tx(&e, ballot(i, i))]);
+    }
+    let mut connection = node.context.pool.acquire().await.unwrap();
+    let (_dir, db_path) = temp_db();
+    let context = Context::new(String::new(), db_path, 0).await.unwrap();
+    let mut fresh = context.pool.acquire().await.unwrap();
+    create_schema(&mut fresh).await.unwrap();
+
+    // content changed without updating the manifest
+    let mut archive = export_election(&mut connection, &e.id()).await.unwrap();
+    archive.archive.nullifiers.pop();
+    assert!(import_election(&mut fresh, &archive).await.is_err());
+
+    // manifest updated but the nullifiers do not match the ballots
+    archive.manifest.hash = archive.archive.hash().unwrap();
+    assert!(import_election(&mut fresh, &archive).await.is_err());
+    assert_eq!(fsck(&mut fresh).await.unwrap(), Vec::<String>::new());
+
+    // an app state that the roots do not hash to
+    let mut archive = export_election(&mut connection, &e.id()).await.unwrap();
+    archive.archive.app_state.hash = hex::encode([0u8; 32]);
+    archive.manifest.hash = archive.archive.hash().unwrap();
+    assert!(import_election(&mut fresh, &archive).await.is_err());
+
+    // an app state of other roots
+    let mut archive = export_election(&mut connection, &e.id()).await.unwrap();
+    archive.archive.app_roots = vec![archive.archive.roots[1].clone()];
+    archive.archive.app_state.hash = hex::encode(app_hash(&[hex::decode(&archive.archive.roots[1]).unwrap()]));
+    archive.manifest.hash = archive.archive.hash().unwrap();
+    assert!(import_election(&mut fresh, &archive).await.is_err());
+}
@@ -0,0 +1,57 @@
+mod common;
+
+use common::{ballot, other_election, temp_db, tx};
+use tendermint_abci::Application;
+use tendermint_proto::abci::RequestFinalizeBlock;
+use zcash_vote_server::{
+    chain::VoteChain,
+    context::Context,
+    db::{create_schema, get_election, get_num_ballots, init_election},
+    fsck::fsck,
+};
+
+#[rocket::async_test]
+async fn backup_waits_for_commit() {
+    let e = other_election("A");
+    let (temp_dir, db_path) = temp_db();
+    let context = Context::new(String::new(), db_path, 0).await.unwrap();
+    let mut connection = context.pool.acquire().await.unwrap();
+    create_schema(&mut connection).await.unwrap();
+    init_election(&mut connection, &e).await.unwrap();
+    let (app, runner) = VoteChain::new(context.pool.clone()).await;
+    runner.spawn();
+
+    let dir = temp_dir.path().join("backup").to_string_lossy().to_string();
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let res = app.finalize_block(RequestFinalizeBlock {
+        txs: vec![tx(&e, ballot(1, 1)).into()],
+        height: 1,
+        ..Default::default()
+    });
+    assert_eq!(res.tx_results[0].code, 0);
+
+    // requested in the middle of the block
+    let backup = {
+        let app = app.clone();
+        let dir = dir.clone();
+        std::thread::spawn(move || app.backup(&dir))
+    };
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    assert!(!backup.is_finished());
+    app.commit();
+
+    let info = backup.join().unwrap().unwrap();
+    assert_eq!(info.height, 1);
+    assert_eq!(info.app_hash, hex::encode(&res.app_hash));
+    assert!(std::path::Path::new(&info.path).with_extension("json").exists());
+
+    let copy = Context::new(String::new(), info.path.clone(), 0).await.unwrap();
+    let mut copy = copy.pool.acquire().await.unwrap();
+    let (id_election, _, _) = get_election(&mut copy, &e.id()).await.unwrap();
+    assert_eq!(get_num_ballots(&mut copy, id_election).await.unwrap(), 1);
+    assert!(fsck(&mut copy).await.unwrap().is_empty());
+
+    // a second backup at the same height is refused
+    assert!(app.backup(&dir).is_err());
+}
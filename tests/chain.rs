@@ -0,0 +1,381 @@
+mod common;
+
+use blake2b_simd::Params;
+use common::{ballot, ballot_spending, dummy_proofs, election, tx, Node};
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+use tendermint_proto::abci::ResponseCheckTx;
+use zcash_vote_server::{
+    chain::{check_ballot_proofs, check_ballot_state, tx_hash, BallotError},
+    db::{get_ballot_block, get_ballot_by_tx_hash, get_election, BlockRef},
+};
+
+fn initial_hash() -> Vec<u8> {
+    Params::new()
+        .hash_length(32)
+        .personal(b"Zcash_Vote_CmBFT")
+        .to_state()
+        .finalize()
+        .as_bytes()
+        .to_vec()
+}
+
+#[rocket::async_test]
+async fn info_on_new_database() {
+    let mut node = Node::start(&[election()]).await;
+    let info = node.info();
+    assert_eq!(info.last_block_height, 0);
+    assert_eq!(info.last_block_app_hash.to_vec(), initial_hash());
+}
+
+#[rocket::async_test]
+async fn empty_blocks_keep_app_hash() {
+    let mut node = Node::start(&[election()]).await;
+    for _ in 0..3 {
+        let res = node.block(vec![]);
+        assert!(res.tx_results.is_empty());
+        assert_eq!(res.app_hash.to_vec(), initial_hash());
+    }
+    let info = node.info();
+    assert_eq!(info.last_block_height, 3);
+    assert_eq!(info.last_block_app_hash.to_vec(), initial_hash());
+}
+
+fn rejection(res: &ResponseCheckTx) -> String {
+    assert_eq!(res.code, 1);
+    String::from_utf8(res.data.to_vec()).unwrap()
+}
+
+#[rocket::async_test]
+async fn check_tx_rejects_malformed_tx() {
+    let mut node = Node::start(&[election()]).await;
+    assert_eq!(rejection(&node.check_tx(vec![])), "Invalid tx");
+    assert_eq!(rejection(&node.check_tx(vec![1, 2, 3])), "Invalid tx");
+}
+
+#[rocket::async_test]
+async fn check_tx_rejects_unknown_election() {
+    let mut node = Node::start(&[]).await;
+    let res = node.check_tx(tx(&election(), ballot(1, 1)));
+    assert_eq!(rejection(&res), "Unknown election");
+}
+
+#[rocket::async_test]
+async fn check_tx_rejects_closed_election() {
+    let e = election();
+    let mut node = Node::start(&[e.clone()]).await;
+    let mut connection = node.context.pool.acquire().await.unwrap();
+    sqlx::query("UPDATE elections SET closed = TRUE")
+        .execute(&mut *connection)
+        .await
+        .unwrap();
+    let res = node.check_tx(tx(&e, ballot(1, 1)));
+    assert_eq!(rejection(&res), "Election is closed");
+}
+
+#[rocket::async_test]
+async fn check_tx_rejects_wrong_anchors() {
+    let e = election();
+    let mut node = Node::start(&[e.clone()]).await;
+
+    let mut b = ballot(1, 1);
+    b.data.anchors.nf[0] ^= 1;
+    assert_eq!(rejection(&node.check_tx(tx(&e, b))), "Incorrect nullifier root");
+
+    let mut b = ballot(1, 1);
+    b.data.anchors.cmx[0] ^= 1;
+    assert_eq!(rejection(&node.check_tx(tx(&e, b))), "Invalid cmx root");
+}
+
+#[rocket::async_test]
+async fn check_tx_rejects_double_spend() {
+    let e = election();
+    let mut node = Node::start(&[e.clone()]).await;
+    node.block(vec![tx(&e, ballot(1, 1))]);
+    // the anchors of the first block are still valid
+    let res = node.check_tx(tx(&e, ballot(1, 2)));
+    assert_eq!(rejection(&res), "Duplicate nullifier: double spend");
+    let res = node.check_tx(tx(&e, ballot_spending(2, 1, 2)));
+    assert_eq!(rejection(&res), "Duplicate nullifier: double spend");
+}
+
+#[rocket::async_test]
+async fn check_tx_rejects_invalid_proof() {
+    let e = election();
+    let mut node = Node::start(&[e.clone()]).await;
+    let b = ballot(1, 1);
+    // the fixture passes every check before the proofs
+    let mut connection = node.context.pool.acquire().await.unwrap();
+    let checked = check_ballot_state(&mut *connection, &e.id(), &b).await.unwrap();
+    let error = check_ballot_proofs(&b, &checked).unwrap_err();
+    assert!(matches!(error, BallotError::InvalidProof(_)));
+
+    let res = node.check_tx(tx(&e, b));
+    assert_eq!(rejection(&res), error.to_string());
+}
+
+#[rocket::async_test]
+async fn check_tx_adds_valid_ballot_to_mempool() {
+    let e = election();
+    let mut node = Node::start_with_proof_check(&[e.clone()], dummy_proofs).await;
+    let b = ballot(1, 1);
+    let t = tx(&e, b.clone());
+    let res = node.check_tx(t.clone());
+    assert_eq!(res.code, 0);
+    assert_eq!(res.data.to_vec(), hex::encode(b.data.sighash().unwrap()).into_bytes());
+    assert!(node.chain.mempool().contains(&tx_hash(&t)));
+    assert!(node.chain.mempool().spends(&e.id(), &[1u8; 32]));
+
+    node.block(vec![t.clone()]);
+    assert!(!node.chain.mempool().contains(&tx_hash(&t)));
+}
+
+#[rocket::async_test]
+async fn failed_recheck_evicts_tx_from_mempool() {
+    let e = election();
+    let mut node = Node::start_with_proof_check(&[e.clone()], dummy_proofs).await;
+    let t = tx(&e, ballot(1, 1));
+    assert_eq!(node.check_tx(t.clone()).code, 0);
+
+    // another ballot spends the nullifier in a block
+    node.block(vec![tx(&e, ballot(1, 2))]);
+    // a new check of the same tx leaves the copy in the mempool
+    assert_eq!(node.check_tx(t.clone()).code, 1);
+    assert!(node.chain.mempool().contains(&tx_hash(&t)));
+    assert_eq!(node.recheck_tx(t.clone()).code, 1);
+    assert!(!node.chain.mempool().contains(&tx_hash(&t)));
+}
+
+#[rocket::async_test]
+async fn unchecked_txs_expire_from_mempool() {
+    let e = election();
+    let mut node = Node::start_with_proof_check(&[e.clone()], dummy_proofs).await;
+    let t = tx(&e, ballot(1, 1));
+    assert_eq!(node.check_tx(t.clone()).code, 0);
+
+    node.chain.mempool().prune(Duration::from_secs(3600));
+    assert!(node.chain.mempool().contains(&tx_hash(&t)));
+    node.chain.mempool().prune(Duration::ZERO);
+    assert!(!node.chain.mempool().contains(&tx_hash(&t)));
+    assert!(!node.chain.mempool().spends(&e.id(), &[1u8; 32]));
+}
+
+#[rocket::async_test]
+async fn finalize_ballots() {
+    let e = election();
+    let mut node = Node::start(&[e.clone()]).await;
+
+    let res = node.block(vec![tx(&e, ballot(1, 1))]);
+    assert_eq!(res.tx_results.len(), 1);
+    assert_eq!(res.tx_results[0].code, 0);
+    let hash1 = res.app_hash.to_vec();
+    assert_ne!(hash1, initial_hash());
+    assert_eq!(node.num_ballots(&e.id()).await, 1);
+
+    let res = node.block(vec![tx(&e, ballot(2, 2))]);
+    assert_eq!(res.tx_results[0].code, 0);
+    assert_ne!(res.app_hash.to_vec(), hash1);
+    assert_eq!(node.num_ballots(&e.id()).await, 2);
+
+    let info = node.info();
+    assert_eq!(info.last_block_height, 2);
+    assert_eq!(info.last_block_app_hash, res.app_hash);
+}
+
+#[rocket::async_test]
+async fn ballots_record_their_block() {
+    let e = election();
+    let mut node = Node::start(&[e.clone()]).await;
+    node.block(vec![]);
+    let t = tx(&e, ballot(1, 1));
+    node.block(vec![t.clone()]);
+
+    let mut connection = node.context.pool.acquire().await.unwrap();
+    let (id_election, _, _) = get_election(&mut connection, &e.id()).await.unwrap();
+    let block = get_ballot_block(&mut connection, id_election, 1).await.unwrap();
+    assert_eq!(
+        block,
+        Some(BlockRef {
+            height: 2,
+            time: 0,
+            tx_hash: hex::encode_upper(Sha256::digest(&t)),
+        })
+    );
+}
+
+#[rocket::async_test]
+async fn rejected_txs_are_recorded() {
+    use tendermint_abci::Application;
+    use tendermint_proto::abci::{RequestCheckTx, RequestFinalizeBlock};
+    use zcash_vote_server::{
+        chain::VoteChain,
+        store::MemoryStore,
+    };
+
+    let e = election();
+    let mut store = MemoryStore::new();
+    store.add_election(&e, false).unwrap();
+    let (app, runner) = VoteChain::with_store(store);
+    runner.spawn();
+
+    // dummy proofs fail check_tx
+    let t1 = tx(&e, ballot(1, 1));
+    let res = app.check_tx(RequestCheckTx {
+        tx: t1.clone().into(),
+        ..Default::default()
+    });
+    assert_eq!(res.code, 1);
+    assert!(app.rejection(&tx_hash(&t1)).is_some());
+
+    // double spend fails finalize_block
+    let t2 = tx(&e, ballot(1, 2));
+    app.finalize_block(RequestFinalizeBlock {
+        txs: vec![t1.clone().into()],
+        height: 1,
+        ..Default::default()
+    });
+    app.commit();
+    app.finalize_block(RequestFinalizeBlock {
+        txs: vec![t2.clone().into()],
+        height: 2,
+        ..Default::default()
+    });
+    app.commit();
+    let error = app.rejection(&tx_hash(&t2)).unwrap();
+    assert!(error.contains("double spend"), "{error}");
+}
+
+#[rocket::async_test]
+async fn tx_results_are_sent_on_commit() {
+    use tendermint_abci::Application;
+    use tendermint_proto::abci::RequestFinalizeBlock;
+    use zcash_vote_server::{
+        chain::VoteChain,
+        store::MemoryStore,
+    };
+
+    let e = election();
+    let mut store = MemoryStore::new();
+    store.add_election(&e, false).unwrap();
+    let (app, runner) = VoteChain::with_store(store);
+    runner.spawn();
+    let mut events = app.subscribe();
+
+    let (t1, t2) = (tx(&e, ballot(1, 1)), tx(&e, ballot(1, 2)));
+    app.finalize_block(RequestFinalizeBlock {
+        txs: vec![t1.clone().into()],
+        height: 1,
+        ..Default::default()
+    });
+    // nothing before the commit
+    assert!(events.try_recv().is_err());
+    app.commit();
+    let event = events.recv().await.unwrap();
+    assert_eq!(event.tx_hash, tx_hash(&t1));
+    assert_eq!(event.error, None);
+
+    app.finalize_block(RequestFinalizeBlock {
+        txs: vec![t2.clone().into()],
+        height: 2,
+        ..Default::default()
+    });
+    app.commit();
+    let event = events.recv().await.unwrap();
+    assert_eq!(event.tx_hash, tx_hash(&t2));
+    assert!(event.error.is_some());
+}
+
+#[rocket::async_test]
+async fn tx_events_match_the_committed_block() {
+    let e = election();
+    let mut node = Node::start(&[e.clone()]).await;
+    let mut events = node.chain.subscribe();
+
+    let txs = vec![
+        tx(&e, ballot(1, 1)),
+        tx(&e, ballot(2, 2)),
+        tx(&e, ballot_spending(3, 1, 3)),
+    ];
+    node.block(txs.clone());
+    let mut connection = node.context.pool.acquire().await.unwrap();
+    for (i, t) in txs.iter().enumerate() {
+        let event = events.recv().await.unwrap();
+        assert_eq!(event.tx_hash, tx_hash(t));
+        let stored = get_ballot_by_tx_hash(&mut connection, &event.tx_hash).await.unwrap();
+        if i < 2 {
+            assert_eq!(event.error, None);
+            assert_eq!(stored, Some((e.id(), i as u32 + 1, 1)));
+        } else {
+            assert!(event.error.is_some());
+            assert_eq!(stored, None);
+        }
+    }
+}
+
+#[rocket::async_test]
+async fn finalize_rejects_double_spend() {
+    let e = election();
+    let mut node = Node::start(&[e.clone()]).await;
+
+    let res = node.block(vec![tx(&e, ballot(1, 1))]);
+    assert_eq!(res.tx_results[0].code, 0);
+    let hash = res.app_hash.to_vec();
+
+    let res = node.block(vec![tx(&e, ballot(1, 2))]);
+    assert_eq!(res.tx_results[0].code, 1);
+    assert_eq!(node.num_ballots(&e.id()).await, 1);
+    assert_eq!(node.info().last_block_app_hash.to_vec(), hash);
+}
+
+#[rocket::async_test]
+async fn rejected_ballot_leaves_no_writes_in_block() {
+    let e = election();
+    let mut node = Node::start(&[e.clone()]).await;
+    node.block(vec![tx(&e, ballot(1, 1))]);
+
+    // the last ballot fails on its second nullifier
+    let res = node.block(vec![
+        tx(&e, ballot(2, 2)),
+        tx(&e, ballot(3, 3)),
+        tx(&e, ballot_spending(5, 1, 5)),
+    ]);
+    let codes = res.tx_results.iter().map(|r| r.code).collect::<Vec<_>>();
+    assert_eq!(codes, vec![0, 0, 1]);
+    assert_eq!(node.num_ballots(&e.id()).await, 3);
+    assert_eq!(node.info().last_block_app_hash, res.app_hash);
+
+    let mut connection = node.context.pool.acquire().await.unwrap();
+    let nfs: Vec<(Vec<u8>,)> = sqlx::query_as("SELECT hash FROM dnfs ORDER BY hash")
+        .fetch_all(&mut *connection)
+        .await
+        .unwrap();
+    let nfs = nfs.into_iter().map(|(nf,)| nf).collect::<Vec<_>>();
+    assert_eq!(nfs, [1u8, 2, 3].map(|nf| [nf; 32].to_vec()).to_vec());
+}
+
+#[rocket::async_test]
+async fn finalize_rejects_closed_election() {
+    let e = election();
+    let mut node = Node::start(&[e.clone()]).await;
+    let mut connection = node.context.pool.acquire().await.unwrap();
+    sqlx::query("UPDATE elections SET closed = TRUE")
+        .execute(&mut *connection)
+        .await
+        .unwrap();
+
+    let res = node.block(vec![tx(&e, ballot(1, 1))]);
+    assert_eq!(res.tx_results[0].code, 1);
+    assert_eq!(res.app_hash.to_vec(), initial_hash());
+    assert_eq!(node.num_ballots(&e.id()).await, 0);
+}
+
+#[rocket::async_test]
+async fn prepare_proposal_filters_double_spend() {
+    let e = election();
+    let mut node = Node::start(&[e.clone()]).await;
+    let tx1 = tx(&e, ballot(1, 1));
+    let tx2 = tx(&e, ballot(1, 2));
+    let tx3 = tx(&e, ballot(3, 3));
+    let txs = node.prepare_proposal(vec![tx1.clone(), tx2, tx3.clone(), vec![0]]);
+    assert_eq!(txs, vec![tx1, tx3]);
+}
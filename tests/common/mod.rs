@@ -0,0 +1,261 @@
+#![allow(dead_code)]
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpListener,
+};
+
+use orchard::vote::Ballot;
+use tempfile::TempDir;
+use tendermint_abci::{Client, ClientBuilder, ServerBuilder};
+use tendermint_proto::abci::{
+    CheckTxType, RequestCheckTx, RequestFinalizeBlock, RequestInfo, RequestPrepareProposal, ResponseCheckTx,
+    ResponseFinalizeBlock, ResponseInfo,
+};
+use zcash_vote::election::Election;
+use zcash_vote_server::{
+    chain::{check_ballot_proofs, BallotError, ProofCheck, VoteChain},
+    context::Context,
+    db::{create_schema, get_cmx_roots, get_election, get_num_ballots, init_election},
+    election::load_elections,
+    routes::Tx,
+};
+
+pub const ELECTION: &str = include_str!("../fixtures/election.json");
+pub const BALLOT: &str = include_str!("../fixtures/ballot.json");
+
+/// A vote server with its own database, driven through the ABCI client
+pub struct Node {
+    pub context: Context,
+    pub client: Client,
+    /// for the tx events
+    pub chain: VoteChain,
+    pub height: i64,
+    /// holds the database, deleted with the node
+    pub dir: TempDir,
+}
+
+impl Node {
+    pub async fn start(elections: &[Election]) -> Self {
+        Self::start_with_proof_check(elections, check_ballot_proofs).await
+    }
+
+    /// Start a node that checks the ballot proofs with `proof_check`
+    pub async fn start_with_proof_check(elections: &[Election], proof_check: ProofCheck) -> Self {
+        let (dir, db_path) = temp_db();
+        let context = Context::new(String::new(), db_path, 0).await.unwrap();
+        let mut connection = context.pool.acquire().await.unwrap();
+        create_schema(&mut connection).await.unwrap();
+        for e in elections.iter() {
+            init_election(&mut connection, e).await.unwrap();
+        }
+        Self::run(context, dir, proof_check).await
+    }
+
+    /// Start a node whose elections are loaded from a data directory.
+    /// `id_offset` placeholder elections are created first and removed so
+    /// that the elections get different `id_election`.
+    pub async fn start_from_data_dir(elections: &[Election], id_offset: u32) -> Self {
+        let (dir, db_path) = temp_db();
+        let data_dir = dir.path().join("data").to_string_lossy().to_string();
+        std::fs::create_dir_all(&data_dir).unwrap();
+        for (i, e) in elections.iter().enumerate() {
+            let path = std::path::Path::new(&data_dir).join(format!("{i}-{}.json", e.name));
+            std::fs::write(path, serde_json::to_string(e).unwrap()).unwrap();
+        }
+
+        let context = Context::new(data_dir, db_path, 0).await.unwrap();
+        let mut connection = context.pool.acquire().await.unwrap();
+        create_schema(&mut connection).await.unwrap();
+        for i in 0..id_offset {
+            sqlx::query("INSERT INTO elections(id, definition, closed) VALUES (?1, '', TRUE)")
+                .bind(format!("placeholder-{i}"))
+                .execute(&mut *connection)
+                .await
+                .unwrap();
+        }
+        load_elections(&mut connection, &context.data_path).await.unwrap();
+        sqlx::query("DELETE FROM elections WHERE id LIKE 'placeholder-%'")
+            .execute(&mut *connection)
+            .await
+            .unwrap();
+        Self::run(context, dir, check_ballot_proofs).await
+    }
+
+    async fn run(context: Context, dir: TempDir, proof_check: ProofCheck) -> Self {
+        let connection = context.pool.acquire().await.unwrap().detach();
+        let (app, runner) = VoteChain::with_proof_check(connection, proof_check);
+        let server = ServerBuilder::new(1_000_000).bind("127.0.0.1:0", app.clone()).unwrap();
+        let addr = server.local_addr();
+        runner.spawn();
+        std::thread::spawn(move || server.listen().unwrap());
+        let client = ClientBuilder::default().connect(addr).unwrap();
+
+        Self {
+            context,
+            client,
+            chain: app,
+            height: 0,
+            dir,
+        }
+    }
+
+    pub fn info(&mut self) -> ResponseInfo {
+        self.client.info(RequestInfo::default()).unwrap()
+    }
+
+    pub fn check_tx(&mut self, tx: Vec<u8>) -> ResponseCheckTx {
+        self.client
+            .check_tx(RequestCheckTx {
+                tx: tx.into(),
+                ..Default::default()
+            })
+            .unwrap()
+    }
+
+    /// check_tx of a tx that CometBFT keeps in its mempool after a block
+    pub fn recheck_tx(&mut self, tx: Vec<u8>) -> ResponseCheckTx {
+        self.client
+            .check_tx(RequestCheckTx {
+                tx: tx.into(),
+                r#type: CheckTxType::Recheck as i32,
+            })
+            .unwrap()
+    }
+
+    pub fn prepare_proposal(&mut self, txs: Vec<Vec<u8>>) -> Vec<Vec<u8>> {
+        let res = self
+            .client
+            .prepare_proposal(RequestPrepareProposal {
+                txs: txs.into_iter().map(Into::into).collect(),
+                ..Default::default()
+            })
+            .unwrap();
+        res.txs.into_iter().map(|tx| tx.to_vec()).collect()
+    }
+
+    /// Finalize and commit the next block
+    pub fn block(&mut self, txs: Vec<Vec<u8>>) -> ResponseFinalizeBlock {
+        self.height += 1;
+        let res = self
+            .client
+            .finalize_block(RequestFinalizeBlock {
+                txs: txs.into_iter().map(Into::into).collect(),
+                height: self.height,
+                ..Default::default()
+            })
+            .unwrap();
+        self.client.commit().unwrap();
+        res
+    }
+
+    /// Committed state of an election: number of ballots and cmx roots
+    pub async fn election_state(&self, id: &str) -> (u32, Vec<Vec<u8>>) {
+        let mut connection = self.context.pool.acquire().await.unwrap();
+        let (id_election, _, _) = get_election(&mut connection, id).await.unwrap();
+        let n = get_num_ballots(&mut connection, id_election).await.unwrap();
+        let roots = get_cmx_roots(&mut connection, id_election).await.unwrap();
+        (n, roots)
+    }
+
+    pub async fn num_ballots(&self, id: &str) -> u32 {
+        let mut connection = self.context.pool.acquire().await.unwrap();
+        let (id_election, _, _) = get_election(&mut connection, id).await.unwrap();
+        get_num_ballots(&mut connection, id_election).await.unwrap()
+    }
+}
+
+pub fn election() -> Election {
+    serde_json::from_str(ELECTION).unwrap()
+}
+
+/// Election fixture with a different name, and therefore id
+pub fn other_election(name: &str) -> Election {
+    let mut election = election();
+    election.name = name.to_string();
+    election
+}
+
+/// Ballot fixture with a single action spending `nf` and creating `cmx`.
+/// Its anchors are the roots of the election fixture but its proofs are
+/// dummies: check_tx rejects it at the proof check, unless the node uses
+/// `dummy_proofs`, and finalize_block accepts it.
+pub fn ballot(nf: u8, cmx: u8) -> Ballot {
+    let mut ballot: Ballot = serde_json::from_str(BALLOT).unwrap();
+    ballot.data.anchors.cmx = election().cmx_frontier.unwrap().root().to_vec();
+    let action = &mut ballot.data.actions[0];
+    action.nf = [nf; 32].to_vec();
+    action.cmx = {
+        let mut cmx_bytes = [0u8; 32];
+        cmx_bytes[0] = cmx;
+        cmx_bytes.to_vec()
+    };
+    ballot
+}
+
+/// Ballot spending `nf` and then `spent`, for a double spend that is
+/// detected after the first nullifier is written
+pub fn ballot_spending(nf: u8, spent: u8, cmx: u8) -> Ballot {
+    let mut ballot = ballot(nf, cmx);
+    let mut action = ballot.data.actions[0].clone();
+    action.nf = [spent; 32].to_vec();
+    ballot.data.actions.push(action);
+    ballot
+}
+
+/// Proof check that accepts the dummy proofs of the ballot fixture, for
+/// the tests of the paths after check_tx
+pub fn dummy_proofs(_ballot: &Ballot, _election: &Election) -> Result<(), BallotError> {
+    Ok(())
+}
+
+pub fn tx(election: &Election, ballot: Ballot) -> Vec<u8> {
+    bincode::serialize(&Tx {
+        id: election.id(),
+        ballot,
+    })
+    .unwrap()
+}
+
+/// A CometBFT RPC that answers every request with `result`
+pub fn fake_node(result: serde_json::Value) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/v1", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            // read the whole request before answering
+            let mut reader = BufReader::new(&mut stream);
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+            let mut body = vec![0u8; content_length];
+            reader.read_exact(&mut body).unwrap();
+            let body = serde_json::json!({ "id": "", "result": result }).to_string();
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            )
+            .unwrap();
+        }
+    });
+    url
+}
+
+/// A temporary directory with the path of a database in it
+pub fn temp_db() -> (TempDir, String) {
+    let dir = tempfile::tempdir().unwrap();
+    let db_path = dir.path().join("vote.db").to_string_lossy().to_string();
+    (dir, db_path)
+}
@@ -0,0 +1,89 @@
+mod common;
+
+use common::{ballot, election, tx, Node};
+use orchard::{
+    keys::{FullViewingKey, Scope, SpendingKey},
+    note::{ExtractedNoteCommitment, Nullifier, RandomSeed},
+    note_encryption::{OrchardDomain, OrchardNoteEncryption},
+    value::NoteValue,
+    vote::BallotAction,
+    Note,
+};
+use zcash_address::{
+    unified::{self, Encoding},
+    Network,
+};
+use zcash_note_encryption::{Domain, COMPACT_NOTE_SIZE};
+use zcash_vote_server::{
+    decryption::{ivk_scalar, prove, verify},
+    tally::{tally, verify_tally},
+};
+
+/// A ballot output of `value` to the address of `fvk`
+fn output(fvk: &FullViewingKey, value: u64) -> ([u8; 43], BallotAction) {
+    let recipient = fvk.address_at(0u32, Scope::External);
+    let rho = Nullifier::from_bytes(&[3u8; 32]).unwrap();
+    let rseed = RandomSeed::from_bytes([5u8; 32], &rho).unwrap();
+    let note = Note::from_parts(recipient, NoteValue::from_raw(value), rho, rseed).unwrap();
+    let encryptor = OrchardNoteEncryption::new(None, note, recipient, [0u8; 512]);
+    let enc = encryptor.encrypt_note_plaintext();
+
+    let mut action = ballot(3, 0).data.actions[0].clone();
+    action.cmx = ExtractedNoteCommitment::from(note.commitment()).to_bytes().to_vec();
+    action.epk = OrchardDomain::epk_bytes(encryptor.epk()).0.to_vec();
+    action.enc = enc[0..COMPACT_NOTE_SIZE].to_vec();
+    (recipient.to_raw_address_bytes(), action)
+}
+
+#[test]
+fn decryption_proof_binds_the_note() {
+    let sk = SpendingKey::from_bytes([7u8; 32]).unwrap();
+    let fvk = FullViewingKey::from(&sk);
+    let ivk = ivk_scalar(&fvk.to_ivk(Scope::External)).unwrap();
+    let (address, action) = output(&fvk, 42_000);
+
+    let proof = prove(&ivk, &address, &action.epk).unwrap();
+    assert_eq!(verify(&proof, &address, &action).unwrap(), 42_000);
+
+    // the compact ciphertext has no tag: flipping a bit of the
+    // value still decrypts, to a note that is not the committed one
+    let mut tampered = action.clone();
+    tampered.enc[12] ^= 1;
+    let error = verify(&proof, &address, &tampered).unwrap_err();
+    assert!(error.to_string().contains("commitment"), "{error}");
+
+    let mut tampered = action.clone();
+    tampered.enc[20] ^= 1;
+    assert!(verify(&proof, &address, &tampered).is_err());
+}
+
+#[rocket::async_test]
+async fn verify_tally_rejects_repeated_outputs() {
+    let sk = SpendingKey::from_bytes([7u8; 32]).unwrap();
+    let fvk = FullViewingKey::from(&sk);
+    let (address, action) = output(&fvk, 42_000);
+    let ua = unified::Address::try_from_items(vec![unified::Receiver::Orchard(address)]).unwrap();
+    let mut election = election();
+    election.candidates[0].address = ua.encode(&Network::Main);
+
+    let mut node = Node::start(&[election.clone()]).await;
+    let mut b = ballot(3, 0);
+    b.data.actions[0] = action;
+    node.block(vec![tx(&election, b)]);
+
+    let mut connection = node.context.pool.acquire().await.unwrap();
+    let mut t = tally(&mut connection, &election.id(), &sk).await.unwrap();
+    assert_eq!(t.candidates[0].amount, 42_000);
+    assert!(verify_tally(&mut connection, &t).await.unwrap().is_empty());
+
+    // the same output listed twice must not count twice
+    let repeated = serde_json::to_value(&t.outputs[0]).unwrap();
+    t.outputs.push(serde_json::from_value(repeated).unwrap());
+    t.candidates[0].amount = 84_000;
+    t.ballots = 2;
+    let errors = verify_tally(&mut connection, &t).await.unwrap();
+    assert_eq!(errors.len(), 3, "{errors:?}");
+    assert!(errors[0].starts_with("Ballots"), "{}", errors[0]);
+    assert!(errors[1].ends_with("Duplicate output"), "{}", errors[1]);
+    assert!(errors[2].starts_with("Candidate 0"), "{}", errors[2]);
+}
@@ -0,0 +1,89 @@
+mod common;
+
+use common::{ballot, other_election, tx, Node};
+use zcash_vote::election::Election;
+
+/// Feed the same blocks to every node and check that they agree
+/// on the results, the app hash and the committed state at every height
+async fn replay(nodes: &mut [Node], elections: &[Election], blocks: &[Vec<Vec<u8>>]) {
+    for (height, txs) in blocks.iter().enumerate() {
+        let results = nodes
+            .iter_mut()
+            .map(|n| n.block(txs.clone()))
+            .collect::<Vec<_>>();
+        for (i, res) in results.iter().enumerate().skip(1) {
+            let codes = |r: &tendermint_proto::abci::ResponseFinalizeBlock| {
+                r.tx_results.iter().map(|t| t.code).collect::<Vec<_>>()
+            };
+            assert_eq!(codes(res), codes(&results[0]), "tx results of node {i} at height {height}");
+            assert_eq!(res.app_hash, results[0].app_hash, "app hash of node {i} at height {height}");
+        }
+
+        let mut infos = vec![];
+        for n in nodes.iter_mut() {
+            infos.push(n.info());
+        }
+        for (i, info) in infos.iter().enumerate() {
+            assert_eq!(info.last_block_height, height as i64 + 1);
+            assert_eq!(
+                info.last_block_app_hash, infos[0].last_block_app_hash,
+                "committed app hash of node {i} at height {height}"
+            );
+        }
+
+        for e in elections.iter() {
+            let s0 = nodes[0].election_state(&e.id()).await;
+            for (i, n) in nodes.iter().enumerate().skip(1) {
+                let s = n.election_state(&e.id()).await;
+                assert_eq!(s, s0, "state of election {} on node {i} at height {height}", e.name);
+            }
+        }
+    }
+}
+
+fn blocks(elections: &[Election]) -> Vec<Vec<Vec<u8>>> {
+    let (a, b, c) = (&elections[0], &elections[1], &elections[2]);
+    vec![
+        vec![tx(a, ballot(1, 1))],
+        vec![],
+        vec![tx(b, ballot(2, 2))],
+        vec![tx(c, ballot(3, 3))],
+        vec![tx(a, ballot(4, 4))],
+        // double spend in the same election
+        vec![tx(a, ballot(1, 5))],
+        // same nullifier in another election
+        vec![tx(c, ballot(1, 6))],
+        vec![tx(b, ballot(7, 7))],
+        vec![],
+        vec![tx(c, ballot(8, 8))],
+    ]
+}
+
+#[rocket::async_test]
+async fn app_hash_is_independent_of_election_order() {
+    let elections = ["A", "B", "C"].map(other_election).to_vec();
+    let mut reversed = elections.clone();
+    reversed.reverse();
+    let rotated = [elections[1].clone(), elections[2].clone(), elections[0].clone()];
+
+    let mut nodes = vec![
+        Node::start(&elections).await,
+        Node::start(&reversed).await,
+        Node::start(&rotated).await,
+    ];
+    replay(&mut nodes, &elections, &blocks(&elections)).await;
+}
+
+#[rocket::async_test]
+async fn app_hash_is_independent_of_election_ids() {
+    let elections = ["A", "B", "C"].map(other_election).to_vec();
+    let mut reversed = elections.clone();
+    reversed.reverse();
+
+    let mut nodes = vec![
+        Node::start_from_data_dir(&elections, 0).await,
+        Node::start_from_data_dir(&reversed, 3).await,
+        Node::start_from_data_dir(&elections, 7).await,
+    ];
+    replay(&mut nodes, &elections, &blocks(&elections)).await;
+}
@@ -0,0 +1,47 @@
+mod common;
+
+use common::{ballot, other_election, tx, Node};
+use serde_json::Value;
+use zcash_vote_server::dump::{diff, dump_state};
+
+async fn dump(node: &Node, height: Option<u32>) -> Value {
+    let mut connection = node.context.pool.acquire().await.unwrap();
+    let dump = dump_state(&mut connection, height).await.unwrap();
+    serde_json::to_value(&dump).unwrap()
+}
+
+#[rocket::async_test]
+async fn dumps_of_nodes_compare() {
+    let elections = ["A", "B"].map(other_election).to_vec();
+    let mut reversed = elections.clone();
+    reversed.reverse();
+    let mut nodes = vec![
+        Node::start(&elections).await,
+        Node::start(&reversed).await,
+        Node::start(&elections).await,
+    ];
+
+    for i in 0..4u8 {
+        let e = &elections[i as usize % 2];
+        for (n, node) in nodes.iter_mut().enumerate() {
+            // the last node gets a different ballot at height 3
+            let cmx = if n == 2 && i == 2 { 100 } else { i };
+            node.block(vec![tx(e, ballot(i, cmx))]);
+        }
+    }
+
+    let d0 = dump(&nodes[0], None).await;
+    let d1 = dump(&nodes[1], None).await;
+    assert_eq!(diff(&d0, &d1), None);
+
+    let d2 = dump(&nodes[2], None).await;
+    let d = diff(&d0, &d2).unwrap();
+    // second ballot of election A
+    assert!(d.starts_with(".elections[") && d.contains("].ballots[1]."), "{d}");
+
+    // the state at height 2 is the same on every node
+    let past = dump(&nodes[0], Some(2)).await;
+    assert_eq!(past["height"], 2);
+    assert_eq!(diff(&past, &dump(&nodes[2], Some(2)).await), None);
+    assert_eq!(past["elections"][0]["ballots"].as_array().unwrap().len(), 1);
+}
@@ -0,0 +1,77 @@
+mod common;
+
+use common::{ballot, other_election, tx, Node};
+use zcash_vote::db::store_prop;
+use zcash_vote_server::{
+    db::{check_schema, create_schema, init_election},
+    fsck::fsck,
+};
+
+#[rocket::async_test]
+async fn election_added_after_the_last_ballot() {
+    let elections = ["B", "C"].map(other_election).to_vec();
+    let mut node = Node::start(&elections).await;
+    for i in 0..4u8 {
+        node.block(vec![tx(&elections[i as usize % 2], ballot(i, i))]);
+    }
+    // empty blocks after the last ballot keep its app hash
+    node.block(vec![]);
+
+    let mut connection = node.context.pool.acquire().await.unwrap();
+    assert_eq!(fsck(&mut connection).await.unwrap(), Vec::<String>::new());
+    // whatever their place in the id order of the app hash
+    for name in ["A", "D"] {
+        init_election(&mut connection, &other_election(name)).await.unwrap();
+        assert_eq!(fsck(&mut connection).await.unwrap(), Vec::<String>::new());
+    }
+
+    store_prop(&mut connection, "state", r#"{"height":5,"hash":"00"}"#).await.unwrap();
+    let errors = fsck(&mut connection).await.unwrap();
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].starts_with("App hash 00"), "{}", errors[0]);
+}
+
+#[rocket::async_test]
+async fn outdated_schema_is_refused() {
+    let e = other_election("A");
+    let mut node = Node::start(&[e.clone()]).await;
+    node.block(vec![tx(&e, ballot(1, 1))]);
+
+    let mut connection = node.context.pool.acquire().await.unwrap();
+    check_schema(&mut connection).await.unwrap();
+
+    // nullifiers of a version without their height
+    sqlx::query("UPDATE dnfs SET height = NULL")
+        .execute(&mut *connection)
+        .await
+        .unwrap();
+    assert!(check_schema(&mut connection).await.is_err());
+    create_schema(&mut connection).await.unwrap();
+    check_schema(&mut connection).await.unwrap();
+
+    sqlx::query("DELETE FROM _sqlx_migrations WHERE version = (SELECT MAX(version) FROM _sqlx_migrations)")
+        .execute(&mut *connection)
+        .await
+        .unwrap();
+    let error = check_schema(&mut connection).await.unwrap_err();
+    assert!(error.to_string().contains("has not run"), "{error}");
+}
+
+#[rocket::async_test]
+async fn tampered_cmx_nodes_are_reported() {
+    let e = other_election("A");
+    let mut node = Node::start(&[e.clone()]).await;
+    for i in 0..4u8 {
+        node.block(vec![tx(&e, ballot(i, i))]);
+    }
+
+    let mut connection = node.context.pool.acquire().await.unwrap();
+    assert_eq!(fsck(&mut connection).await.unwrap(), Vec::<String>::new());
+    sqlx::query("UPDATE cmx_nodes SET hash = zeroblob(32) WHERE level = 1")
+        .execute(&mut *connection)
+        .await
+        .unwrap();
+    let errors = fsck(&mut connection).await.unwrap();
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].contains("cmx nodes"), "{}", errors[0]);
+}
@@ -0,0 +1,59 @@
+mod common;
+
+use common::{ballot, fake_node, other_election, tx, Node};
+use zcash_vote_server::{
+    chain::tx_hash,
+    outbox::{enqueue, get_entry, now, process},
+    rpc::CometRpc,
+};
+
+#[rocket::async_test]
+async fn queued_ballot_is_retried_until_committed() {
+    let e = other_election("A");
+    let mut node = Node::start(&[e.clone()]).await;
+    let t = tx(&e, ballot(1, 1));
+    let mut connection = node.context.pool.acquire().await.unwrap();
+    let id = enqueue(&mut connection, &e.id(), &t).await.unwrap();
+    assert_eq!(enqueue(&mut connection, &e.id(), &t).await.unwrap(), id);
+
+    // nothing listens on port 1
+    let down = CometRpc::with_url("http://127.0.0.1:1/v1".to_string());
+    let now = now();
+    process(&mut connection, &down, now).await.unwrap();
+    let entry = get_entry(&mut connection, id).await.unwrap().unwrap();
+    assert_eq!((entry.status.as_str(), entry.attempts), ("queued", 1));
+    assert!(entry.error.is_some());
+    // not retried before the backoff
+    process(&mut connection, &down, now).await.unwrap();
+    let entry = get_entry(&mut connection, id).await.unwrap().unwrap();
+    assert_eq!(entry.attempts, 1);
+
+    let up = CometRpc::with_url(fake_node(serde_json::json!({ "code": 0, "hash": tx_hash(&t) })));
+    process(&mut connection, &up, now + 10).await.unwrap();
+    let entry = get_entry(&mut connection, id).await.unwrap().unwrap();
+    assert_eq!((entry.status.as_str(), entry.attempts), ("pending", 2));
+    assert_eq!(entry.error, None);
+
+    node.block(vec![t.clone()]);
+    process(&mut connection, &up, now + 10).await.unwrap();
+    let entry = get_entry(&mut connection, id).await.unwrap().unwrap();
+    assert_eq!(entry.status, "committed");
+}
+
+#[rocket::async_test]
+async fn rejected_ballot_is_not_retried() {
+    let e = other_election("A");
+    let node = Node::start(&[e.clone()]).await;
+    let t = tx(&e, ballot(1, 1));
+    let mut connection = node.context.pool.acquire().await.unwrap();
+    let id = enqueue(&mut connection, &e.id(), &t).await.unwrap();
+
+    let rpc = CometRpc::with_url(fake_node(serde_json::json!({ "code": 1, "data": "Double spend" })));
+    let now = now();
+    process(&mut connection, &rpc, now).await.unwrap();
+    process(&mut connection, &rpc, now + 3600).await.unwrap();
+    let entry = get_entry(&mut connection, id).await.unwrap().unwrap();
+    assert_eq!(entry.status, "rejected");
+    assert_eq!(entry.attempts, 1);
+    assert_eq!(entry.error.as_deref(), Some("Double spend"));
+}
@@ -0,0 +1,46 @@
+mod common;
+
+use common::{ballot, other_election, tx, Node};
+use zcash_vote_server::{fsck::fsck, rollback::rollback};
+
+#[rocket::async_test]
+async fn rollback_and_replay() {
+    let elections = ["A", "B"].map(other_election).to_vec();
+    let mut node = Node::start(&elections).await;
+    let blocks = (0..6u8)
+        .map(|i| vec![tx(&elections[i as usize % 2], ballot(i, i))])
+        .collect::<Vec<_>>();
+
+    let mut app_hashes = vec![];
+    for txs in blocks.iter() {
+        node.block(txs.clone());
+        app_hashes.push(node.info().last_block_app_hash);
+    }
+    let mut expected = vec![];
+    for e in elections.iter() {
+        expected.push(node.election_state(&e.id()).await);
+    }
+
+    {
+        let mut connection = node.context.pool.acquire().await.unwrap();
+        let app_state = rollback(&mut connection, 3).await.unwrap();
+        assert_eq!(app_state.height, 3);
+        assert!(fsck(&mut connection).await.unwrap().is_empty());
+        // future heights are unknown
+        assert!(rollback(&mut connection, 4).await.is_err());
+    }
+    let info = node.info();
+    assert_eq!(info.last_block_height, 3);
+    assert_eq!(info.last_block_app_hash, app_hashes[2]);
+    assert_eq!(node.election_state(&elections[0].id()).await.0, 2);
+
+    node.height = 3;
+    for (i, txs) in blocks.iter().enumerate().skip(3) {
+        let res = node.block(txs.clone());
+        assert_eq!(res.tx_results[0].code, 0, "ballot {i} is accepted again");
+        assert_eq!(node.info().last_block_app_hash, app_hashes[i]);
+    }
+    for (e, s) in elections.iter().zip(expected.iter()) {
+        assert_eq!(&node.election_state(&e.id()).await, s);
+    }
+}
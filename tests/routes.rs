@@ -0,0 +1,389 @@
+mod common;
+
+use std::{net::SocketAddr, sync::Arc};
+
+use common::{ballot, dummy_proofs, fake_node, other_election, tx, Node};
+use rocket::{
+    http::{Header, Status},
+    local::asynchronous::Client,
+    routes,
+};
+use serde_json::Value;
+use zcash_vote_server::{
+    chain::tx_hash,
+    context::Context,
+    db::index_nullifiers,
+    outbox::enqueue,
+    rpc::CometRpc,
+    routes::{
+        get_ballot_by_sighash, get_ballots, get_ballots_ndjson, get_compact_ballots,
+        get_elections, get_nullifier, get_queue_status, get_tx_status, post_backup, post_ballot,
+    },
+};
+
+/// REST client on the database of a node
+async fn client(node: &Node) -> Client {
+    client_with(node, |_| {}).await
+}
+
+/// REST client on the database of a node, with its context changed by `f`
+async fn client_with(node: &Node, f: impl FnOnce(&mut Context)) -> Client {
+    let mut context = Context::new(String::new(), node.context.db_path.clone(), 0)
+        .await
+        .unwrap();
+    f(&mut context);
+    let rocket = rocket::build()
+        .manage(context)
+        .manage(node.chain.clone())
+        .mount("/", routes![
+            get_elections,
+            get_ballots,
+            get_ballots_ndjson,
+            get_compact_ballots,
+            get_nullifier,
+            get_ballot_by_sighash,
+            get_tx_status,
+            get_queue_status,
+            post_ballot,
+            post_backup
+        ]);
+    Client::tracked(rocket).await.unwrap()
+}
+
+async fn get_json(client: &Client, uri: &str) -> Value {
+    let res = client.get(uri).dispatch().await;
+    assert_eq!(res.status(), Status::Ok, "{uri}");
+    res.into_json().await.unwrap()
+}
+
+#[rocket::async_test]
+async fn list_elections() {
+    let elections = ["A", "B", "C"].map(other_election).to_vec();
+    let mut node = Node::start(&elections).await;
+    node.block(vec![tx(&elections[0], ballot(1, 1))]);
+    {
+        let mut connection = node.context.pool.acquire().await.unwrap();
+        sqlx::query("UPDATE elections SET closed = TRUE WHERE id = ?1")
+            .bind(elections[1].id())
+            .execute(&mut *connection)
+            .await
+            .unwrap();
+    }
+    let client = client(&node).await;
+
+    let list = get_json(&client, "/elections").await;
+    assert_eq!(list["total"], 3);
+    let items = list["elections"].as_array().unwrap();
+    assert_eq!(items.len(), 3);
+    let a = items.iter().find(|e| e["name"] == "A").unwrap();
+    assert_eq!(a["id"], elections[0].id());
+    assert_eq!(a["num_ballots"], 1);
+    assert_eq!(a["closed"], false);
+    assert_eq!(a["candidates"].as_array().unwrap().len(), 2);
+    assert_eq!(a["candidates"][0]["choice"], "Yes");
+    assert_eq!(a["start_height"], elections[0].start_height);
+    assert_eq!(a["signature_required"], false);
+    assert_eq!(a["nf"], hex::encode(elections[0].nf.0));
+    assert_eq!(a["cmx"], hex::encode(elections[0].cmx.0));
+    assert!(a.get("cmx_frontier").is_none());
+
+    let closed = get_json(&client, "/elections?closed=true").await;
+    assert_eq!(closed["total"], 1);
+    assert_eq!(closed["elections"][0]["name"], "B");
+    let open = get_json(&client, "/elections?closed=false").await;
+    assert_eq!(open["total"], 2);
+
+    let page = get_json(&client, "/elections?offset=1&limit=1").await;
+    assert_eq!(page["total"], 3);
+    assert_eq!(page["elections"].as_array().unwrap().len(), 1);
+    assert_eq!(page["elections"][0]["id"], items[1]["id"]);
+}
+
+#[rocket::async_test]
+async fn ballot_pages_and_stream() {
+    let e = other_election("A");
+    let mut node = Node::start(&[e.clone()]).await;
+    for i in 0..25u8 {
+        node.block(vec![tx(&e, ballot(i, i))]);
+    }
+    let client = client(&node).await;
+    let id = e.id();
+
+    let mut heights = vec![];
+    let mut from = 1;
+    loop {
+        let page = get_json(&client, &format!("/election/{id}/ballots?from={from}&limit=10")).await;
+        for b in page["ballots"].as_array().unwrap() {
+            heights.push(b["height"].as_u64().unwrap());
+            assert_eq!(b["block"]["height"], b["height"]);
+        }
+        match page["next"].as_u64() {
+            Some(next) => from = next,
+            None => break,
+        }
+    }
+    assert_eq!(heights, (1..=25).collect::<Vec<_>>());
+
+    let page = get_json(&client, &format!("/election/{id}/ballots?from=5&to=7")).await;
+    assert_eq!(page["ballots"].as_array().unwrap().len(), 3);
+    assert_eq!(page["next"], Value::Null);
+
+    let res = client.get(format!("/election/{id}/ballots.ndjson")).dispatch().await;
+    assert_eq!(res.status(), Status::Ok);
+    let body = res.into_string().await.unwrap();
+    let lines = body.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 25);
+    for (i, line) in lines.iter().enumerate() {
+        let b: Value = serde_json::from_str(line).unwrap();
+        assert_eq!(b["height"], i as u64 + 1);
+    }
+}
+
+#[rocket::async_test]
+async fn compact_ballots() {
+    let e = other_election("A");
+    let mut node = Node::start(&[e.clone()]).await;
+    for i in 0..5u8 {
+        node.block(vec![tx(&e, ballot(i, i))]);
+    }
+    let client = client(&node).await;
+
+    let page = get_json(&client, &format!("/election/{}/compact_ballots?from=2&limit=2", e.id())).await;
+    let ballots = page["ballots"].as_array().unwrap();
+    assert_eq!(ballots.len(), 2);
+    assert_eq!(page["next"], 4);
+    let b = &ballots[0];
+    assert_eq!(b["height"], 2);
+    let a = &b["actions"][0];
+    assert_eq!(a["nf"], hex::encode([1u8; 32]));
+    assert_eq!(a["enc"].as_str().unwrap().len(), 52 * 2);
+    // no proofs
+    assert!(b.get("witnesses").is_none() && b.get("data").is_none());
+}
+
+#[rocket::async_test]
+async fn nullifier_status() {
+    let e = other_election("A");
+    let mut node = Node::start_with_proof_check(&[e.clone()], dummy_proofs).await;
+    for i in 1..=3u8 {
+        node.block(vec![tx(&e, ballot(i, i))]);
+    }
+    let client = client(&node).await;
+    let uri = |nf: u8| format!("/election/{}/nullifier/{}", e.id(), hex::encode([nf; 32]));
+
+    let status = get_json(&client, &uri(2)).await;
+    assert_eq!(status["spent"], true);
+    assert_eq!(status["height"], 2);
+    assert_eq!(status["sighash"], hex::encode(ballot(2, 2).data.sighash().unwrap()));
+    assert_eq!(status["pending"], Value::Null);
+
+    let status = get_json(&client, &uri(9)).await;
+    assert_eq!(status["spent"], false);
+    assert_eq!(status["height"], Value::Null);
+
+    // nullifiers stored before the height column are indexed on startup
+    {
+        let mut connection = node.context.pool.acquire().await.unwrap();
+        sqlx::query("UPDATE dnfs SET height = NULL")
+            .execute(&mut *connection)
+            .await
+            .unwrap();
+        index_nullifiers(&mut connection).await.unwrap();
+    }
+    let status = get_json(&client, &uri(3)).await;
+    assert_eq!(status["height"], 3);
+
+    let t = tx(&e, ballot(4, 4));
+    assert_eq!(node.check_tx(t.clone()).code, 0);
+    let status = get_json(&client, &format!("{}?mempool=true", uri(4))).await;
+    assert_eq!((&status["spent"], &status["pending"]), (&Value::Bool(false), &Value::Bool(true)));
+    let status = get_json(&client, &format!("/tx/{}/status", tx_hash(&t))).await;
+    assert_eq!(status["status"], "pending");
+
+    node.block(vec![t.clone()]);
+    let status = get_json(&client, &format!("{}?mempool=true", uri(4))).await;
+    assert_eq!((&status["spent"], &status["pending"]), (&Value::Bool(true), &Value::Bool(false)));
+    let status = get_json(&client, &format!("/tx/{}/status", tx_hash(&t))).await;
+    assert_eq!(status["status"], "committed");
+}
+
+#[rocket::async_test]
+async fn ballot_by_sighash_and_tx_status() {
+    let e = other_election("A");
+    let mut node = Node::start(&[e.clone()]).await;
+    node.block(vec![]);
+    let b = ballot(1, 1);
+    let t = tx(&e, b.clone());
+    node.block(vec![t.clone()]);
+    let client = client(&node).await;
+
+    let sighash = hex::encode(b.data.sighash().unwrap());
+    let ballot = get_json(&client, &format!("/election/{}/ballot/{sighash}", e.id())).await;
+    assert_eq!(ballot["height"], 1);
+    assert_eq!(ballot["block"]["height"], 2);
+    let res = client
+        .get(format!("/election/{}/ballot/{}", e.id(), hex::encode([0u8; 32])))
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::NotFound);
+
+    // lower case hashes are accepted
+    let status = get_json(&client, &format!("/tx/{}/status", tx_hash(&t).to_lowercase())).await;
+    assert_eq!(status["status"], "committed");
+    assert_eq!(status["election"], e.id());
+    assert_eq!(status["height"], 1);
+    assert_eq!(status["block_height"], 2);
+}
+
+#[rocket::async_test]
+async fn queue_status() {
+    let e = other_election("A");
+    let mut node = Node::start(&[e.clone()]).await;
+    let t = tx(&e, ballot(1, 1));
+    let id = {
+        let mut connection = node.context.pool.acquire().await.unwrap();
+        enqueue(&mut connection, &e.id(), &t).await.unwrap()
+    };
+    let client = client(&node).await;
+
+    let status = get_json(&client, &format!("/queue/{id}")).await;
+    assert_eq!(status["status"], "queued");
+    assert_eq!(status["tx_hash"], tx_hash(&t));
+    assert_eq!(status["attempts"], 0);
+
+    // committed before the outbox noticed
+    node.block(vec![t]);
+    let status = get_json(&client, &format!("/queue/{id}")).await;
+    assert_eq!(status["status"], "committed");
+    assert_eq!(status["height"], 1);
+
+    let res = client.get(format!("/queue/{}", id + 1)).dispatch().await;
+    assert_eq!(res.status(), Status::NotFound);
+}
+
+#[rocket::async_test]
+async fn ballots_are_checked_before_broadcast() {
+    let elections = ["A", "B"].map(other_election).to_vec();
+    let mut node = Node::start(&elections).await;
+    node.block(vec![tx(&elections[0], ballot(1, 1))]);
+    {
+        let mut connection = node.context.pool.acquire().await.unwrap();
+        sqlx::query("UPDATE elections SET closed = TRUE WHERE id = ?1")
+            .bind(elections[1].id())
+            .execute(&mut *connection)
+            .await
+            .unwrap();
+    }
+    let client = client(&node).await;
+    let post = |id: String, nf: u8| {
+        let client = &client;
+        async move {
+            let res = client.post(format!("/election/{id}/ballot")).json(&ballot(nf, 2)).dispatch().await;
+            (res.status(), res.into_string().await.unwrap_or_default())
+        }
+    };
+
+    assert_eq!(post("unknown".to_string(), 2).await.0, Status::NotFound);
+    assert_eq!(post(elections[1].id(), 2).await, (Status::Conflict, "Election is closed".to_string()));
+    let (status, error) = post(elections[0].id(), 1).await;
+    assert_eq!(status, Status::Conflict);
+    assert!(error.contains("double spend"), "{error}");
+    // the fixture has dummy proofs
+    assert_eq!(post(elections[0].id(), 2).await.0, Status::BadRequest);
+}
+
+#[rocket::async_test]
+async fn checked_ballots_are_broadcast() {
+    let e = other_election("A");
+    let node = Node::start_with_proof_check(&[e.clone()], dummy_proofs).await;
+    let rpc = fake_node(serde_json::json!({ "code": 0, "hash": "ABCD" }));
+    let client = client_with(&node, |context| context.rpc = Arc::new(CometRpc::with_url(rpc))).await;
+
+    let res = client
+        .post(format!("/election/{}/ballot", e.id()))
+        .json(&ballot(1, 1))
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::Ok);
+    assert_eq!(res.into_string().await.unwrap(), "ABCD");
+}
+
+#[rocket::async_test]
+async fn wait_modes_of_posted_ballots() {
+    let e = other_election("A");
+    let node = Node::start_with_proof_check(&[e.clone()], dummy_proofs).await;
+    let rpc = fake_node(serde_json::json!({ "code": 0, "hash": "ABCD" }));
+    let client = client_with(&node, |context| context.rpc = Arc::new(CometRpc::with_url(rpc))).await;
+    let post = |wait: &str| {
+        client
+            .post(format!("/election/{}/ballot?wait={wait}", e.id()))
+            .json(&ballot(1, 1))
+            .dispatch()
+    };
+
+    for wait in ["async", "sync"] {
+        let res = post(wait).await;
+        assert_eq!(res.status(), Status::Ok, "{wait}");
+        assert_eq!(res.into_string().await.unwrap(), "ABCD");
+    }
+    assert_eq!(post("block").await.status(), Status::BadRequest);
+}
+
+#[rocket::async_test]
+async fn commit_wait_finds_ballot_of_a_missed_event() {
+    let e = other_election("A");
+    let mut node = Node::start_with_proof_check(&[e.clone()], dummy_proofs).await;
+    // the node answers with a tx that is committed before the request
+    // subscribes to the events
+    let t = tx(&e, ballot(2, 2));
+    node.block(vec![t.clone()]);
+    let rpc = fake_node(serde_json::json!({ "code": 0, "hash": tx_hash(&t) }));
+    let client = client_with(&node, |context| {
+        context.rpc = Arc::new(CometRpc::with_url(rpc));
+        context.commit_timeout = 1;
+    })
+    .await;
+
+    let res = client
+        .post(format!("/election/{}/ballot?wait=commit", e.id()))
+        .json(&ballot(1, 1))
+        .dispatch()
+        .await;
+    assert_eq!(res.status(), Status::Ok);
+    let status: Value = res.into_json().await.unwrap();
+    assert_eq!(status["status"], "committed");
+    assert_eq!(status["election"], e.id());
+    assert_eq!(status["height"], 1);
+}
+
+#[rocket::async_test]
+async fn backup_requires_the_admin_token() {
+    let e = other_election("A");
+    let mut node = Node::start(&[e.clone()]).await;
+    node.block(vec![tx(&e, ballot(1, 1))]);
+    let dir = node.dir.path().join("backup").to_string_lossy().to_string();
+    std::fs::create_dir_all(&dir).unwrap();
+    let client = client_with(&node, |context| {
+        context.backup_dir = Some(dir.clone());
+        context.admin_token = Some("secret".to_string());
+    })
+    .await;
+    let local = "127.0.0.1:9000".parse::<SocketAddr>().unwrap();
+    let post = |token: Option<&'static str>, remote: SocketAddr| {
+        let mut req = client.post("/admin/backup").remote(remote);
+        if let Some(token) = token {
+            req = req.header(Header::new("Authorization", format!("Bearer {token}")));
+        }
+        req.dispatch()
+    };
+
+    assert_eq!(post(None, local).await.status(), Status::Forbidden);
+    assert_eq!(post(Some("wrong"), local).await.status(), Status::Forbidden);
+    let remote = "10.0.0.1:9000".parse().unwrap();
+    assert_eq!(post(Some("secret"), remote).await.status(), Status::Forbidden);
+
+    let res = post(Some("secret"), local).await;
+    assert_eq!(res.status(), Status::Ok);
+    let info: Value = res.into_json().await.unwrap();
+    assert_eq!(info["height"], 1);
+}
@@ -0,0 +1,43 @@
+mod common;
+
+use common::fake_node;
+use zcash_vote_server::rpc::{CometRpc, RpcEndpoint, RpcStrategy};
+
+fn endpoint(url: &str) -> RpcEndpoint {
+    RpcEndpoint {
+        url: url.to_string(),
+        timeout: 5,
+    }
+}
+
+#[rocket::async_test]
+async fn failover_to_next_endpoint() {
+    // nothing listens on port 1
+    let down = "http://127.0.0.1:1/v1";
+    let up = fake_node(serde_json::json!({ "code": 0, "hash": "ABCD" }));
+    let rpc = CometRpc::with_endpoints(
+        vec![endpoint(down), endpoint(&up)],
+        RpcStrategy::Failover,
+    );
+
+    assert_eq!(rpc.broadcast_tx_sync(&[1, 2, 3]).await.unwrap(), "ABCD");
+    assert_eq!(rpc.health(), vec![(down.to_string(), false), (up.clone(), true)]);
+    // the endpoint stays down until a health check succeeds
+    rpc.check_health().await;
+    assert!(!rpc.health()[0].1);
+    assert_eq!(rpc.broadcast_tx_async(&[1, 2, 3]).await.unwrap(), "ABCD");
+
+    let rpc = CometRpc::with_endpoints(vec![endpoint(down)], RpcStrategy::RoundRobin);
+    assert!(rpc.broadcast_tx_sync(&[1, 2, 3]).await.is_err());
+}
+
+#[rocket::async_test]
+async fn rejected_tx_is_not_retried() {
+    let a = fake_node(serde_json::json!({ "code": 1, "data": "Double spend" }));
+    let b = fake_node(serde_json::json!({ "code": 0, "hash": "ABCD" }));
+    let rpc = CometRpc::with_endpoints(vec![endpoint(&a), endpoint(&b)], RpcStrategy::Failover);
+
+    let error = rpc.broadcast_tx_sync(&[1, 2, 3]).await.unwrap_err();
+    assert_eq!(error.to_string(), "Double spend");
+    assert!(rpc.health().iter().all(|(_, healthy)| *healthy));
+}
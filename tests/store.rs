@@ -0,0 +1,107 @@
+mod common;
+
+use common::{ballot, ballot_spending, other_election, tx};
+use tendermint_abci::Application;
+use tendermint_proto::abci::{RequestFinalizeBlock, ResponseFinalizeBlock};
+use zcash_vote_server::{chain::VoteChain, store::MemoryStore};
+
+fn block(app: &VoteChain, height: i64, txs: Vec<Vec<u8>>) -> ResponseFinalizeBlock {
+    let res = app.finalize_block(RequestFinalizeBlock {
+        txs: txs.into_iter().map(Into::into).collect(),
+        height,
+        ..Default::default()
+    });
+    app.commit();
+    res
+}
+
+#[rocket::async_test]
+async fn memory_store_matches_sqlite() {
+    let elections = ["A", "B"].map(other_election).to_vec();
+    let mut node = common::Node::start(&elections).await;
+
+    let mut store = MemoryStore::new();
+    for e in elections.iter() {
+        store.add_election(e, false).unwrap();
+    }
+    let (app, runner) = VoteChain::with_store(store);
+    runner.spawn();
+
+    for i in 0..50u8 {
+        let e = &elections[i as usize % 2];
+        // every 10th ballot reuses a nullifier
+        let nf = if i % 10 == 9 { i - 1 } else { i };
+        let txs = vec![tx(e, ballot(nf, i))];
+        let expected = node.block(txs.clone());
+        let res = block(&app, i as i64 + 1, txs);
+        assert_eq!(res.tx_results[0].code, expected.tx_results[0].code);
+        assert_eq!(res.app_hash, expected.app_hash, "app hash at height {}", i + 1);
+    }
+}
+
+#[rocket::async_test]
+async fn memory_store_matches_sqlite_in_blocks_of_several_ballots() {
+    let e = other_election("A");
+    let mut node = common::Node::start(&[e.clone()]).await;
+
+    let mut store = MemoryStore::new();
+    store.add_election(&e, false).unwrap();
+    let (app, runner) = VoteChain::with_store(store);
+    runner.spawn();
+
+    for i in 0..10u8 {
+        let n = 10 * i;
+        let txs = vec![
+            tx(&e, ballot(n + 1, n + 1)),
+            // fails after writing its first nullifier
+            tx(&e, ballot_spending(n + 2, n + 1, n + 2)),
+            tx(&e, ballot(n + 3, n + 3)),
+        ];
+        let expected = node.block(txs.clone());
+        let res = block(&app, i as i64 + 1, txs);
+        let codes = |r: &ResponseFinalizeBlock| r.tx_results.iter().map(|t| t.code).collect::<Vec<_>>();
+        assert_eq!(codes(&expected), vec![0, 1, 0]);
+        assert_eq!(codes(&res), codes(&expected));
+        assert_eq!(res.app_hash, expected.app_hash, "app hash at height {}", i + 1);
+    }
+    assert_eq!(node.num_ballots(&e.id()).await, 20);
+}
+
+#[rocket::async_test]
+async fn simulate_blocks_in_memory() {
+    let e = other_election("A");
+    let mut store = MemoryStore::new();
+    store.add_election(&e, false).unwrap();
+    let (app, runner) = VoteChain::with_store(store);
+    runner.spawn();
+
+    for height in 1..=1000i64 {
+        let txs = vec![tx(&e, ballot((height % 251) as u8, (height % 256) as u8))];
+        let res = block(&app, height, txs);
+        for r in res.tx_results.iter() {
+            // nullifiers cycle every 251 blocks and are rejected after the first round
+            assert_eq!(r.code == 0, height <= 251);
+        }
+    }
+}
+
+#[rocket::async_test]
+async fn blocks_below_block_tx_height_keep_their_last_ballot() {
+    let e = other_election("A");
+    let chain = |block_tx_height: u32| {
+        let mut store = MemoryStore::new();
+        store.add_election(&e, false).unwrap();
+        let (app, runner) = VoteChain::with_store(store);
+        runner.with_block_tx_height(block_tx_height).spawn();
+        app
+    };
+    let legacy = chain(2);
+    let expected = chain(0);
+
+    let res = block(&legacy, 1, vec![tx(&e, ballot(1, 1)), tx(&e, ballot(2, 2))]);
+    assert_eq!(res.tx_results.iter().map(|r| r.code).collect::<Vec<_>>(), vec![0, 0]);
+    assert_eq!(res.app_hash, block(&expected, 1, vec![tx(&e, ballot(2, 2))]).app_hash);
+
+    let txs = vec![tx(&e, ballot(3, 3)), tx(&e, ballot(4, 4))];
+    assert_eq!(block(&legacy, 2, txs.clone()).app_hash, block(&expected, 2, txs).app_hash);
+}
@@ -0,0 +1,75 @@
+mod common;
+
+use common::{ballot, other_election, tx, Node};
+use incrementalmerkletree::{Altitude, Hashable};
+use orchard::{tree::MerkleHashOrchard, vote::OrchardHash};
+use zcash_vote_server::{db::get_election, witness::witness};
+
+fn hash(s: &str) -> MerkleHashOrchard {
+    let bytes: [u8; 32] = hex::decode(s).unwrap().try_into().unwrap();
+    MerkleHashOrchard::from_bytes(&bytes).unwrap()
+}
+
+/// Fold the path from the leaf to the root
+fn root(cmx: &str, position: u64, path: &[String]) -> String {
+    let mut node = hash(cmx);
+    for (h, sibling) in path.iter().enumerate() {
+        let level = Altitude::from(h as u8);
+        let sibling = hash(sibling);
+        node = if (position >> h) & 1 == 0 {
+            MerkleHashOrchard::combine(level, &node, &sibling)
+        } else {
+            MerkleHashOrchard::combine(level, &sibling, &node)
+        };
+    }
+    hex::encode(node.to_bytes())
+}
+
+/// Check the witnesses of the outputs of 7 ballots against the roots of the chain
+async fn check_witnesses(e: &zcash_vote::election::Election) -> u64 {
+    let mut node = Node::start(&[e.clone()]).await;
+    for i in 1..=7u8 {
+        node.block(vec![tx(e, ballot(i, i))]);
+    }
+    let mut connection = node.context.pool.acquire().await.unwrap();
+    let (id_election, _, _) = get_election(&mut connection, &e.id()).await.unwrap();
+    let (_, roots) = node.election_state(&e.id()).await;
+    let p0 = e.cmx_frontier.as_ref().unwrap().position as u64;
+
+    for (cmx, anchor_height) in [(3u8, None), (3, Some(3)), (7, Some(7)), (1, Some(5))] {
+        let mut cmx_bytes = [0u8; 32];
+        cmx_bytes[0] = cmx;
+        let w = witness(&mut connection, id_election, &cmx_bytes, anchor_height)
+            .await
+            .unwrap();
+        assert_eq!(w.position, p0 + cmx as u64);
+        assert_eq!(w.path.len(), 32);
+        assert_eq!(w.anchor, hex::encode(&roots[w.anchor_height as usize]));
+        assert_eq!(root(&w.cmx, w.position, &w.path), w.anchor);
+    }
+
+    // not in the tree yet at this anchor
+    let mut cmx_bytes = [0u8; 32];
+    cmx_bytes[0] = 6;
+    assert!(witness(&mut connection, id_election, &cmx_bytes, Some(5)).await.is_err());
+    p0
+}
+
+#[rocket::async_test]
+async fn witness_matches_anchor() {
+    check_witnesses(&other_election("A")).await;
+}
+
+#[rocket::async_test]
+async fn witness_uses_the_ommers_of_the_initial_frontier() {
+    let mut e = other_election("A");
+    let frontier = e.cmx_frontier.as_mut().unwrap();
+    for i in 0..10u8 {
+        let mut leaf = [0u8; 32];
+        leaf[1] = i + 1;
+        frontier.append(OrchardHash(leaf));
+    }
+    // position 10 has ommers at levels 1 and 3
+    assert_eq!(frontier.ommers.len(), 2);
+    assert_eq!(check_witnesses(&e).await, 10);
+}